@@ -1,25 +1,24 @@
 use std::collections::HashMap;
 use std::fs::File;
-use std::io::{Read, Cursor};
-use std::path::Path;
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use zip::read::ZipFile;
 use zip::ZipArchive;
 
+use crate::database::{read_varint, unique_temp_path};
 use crate::error::AnkiError;
+use crate::media::{
+    decompress_gzip_limited, decompress_zstd_limited, is_gzip_compressed, is_zstd_compressed,
+};
 
-/// Decompress zstd-compressed data
-fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, AnkiError> {
-    // Check for zstd magic bytes: 28 B5 2F FD
-    if data.len() < 4 || data[0..4] != [0x28, 0xB5, 0x2F, 0xFD] {
-        // Not zstd compressed, return as-is
-        return Ok(data.to_vec());
-    }
+/// Default cap on the decompressed collection database size (2 GB)
+pub const DEFAULT_MAX_DATABASE_BYTES: u64 = 2 * 1024 * 1024 * 1024;
 
-    zstd::decode_all(data)
-        .map_err(|e| AnkiError::DecompressionError(e.to_string()))
-}
+/// Zstd frame magic bytes
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
 
 /// Detected Anki archive format
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
 pub enum AnkiFormat {
     /// Legacy format: collection.anki2 (SQLite, no compression)
     Legacy,
@@ -38,54 +37,105 @@ impl AnkiFormat {
             AnkiFormat::Compressed => "collection.anki21b",
         }
     }
+
+    /// Get the format for a database filename (without any folder prefix)
+    pub fn from_db_filename(filename: &str) -> Option<Self> {
+        [AnkiFormat::Compressed, AnkiFormat::Modern, AnkiFormat::Legacy]
+            .into_iter()
+            .find(|format| format.db_filename() == filename)
+    }
+}
+
+/// Package metadata from the `meta` entry of modern exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PackageMeta {
+    /// Package version: 1 = legacy (.anki2), 2 = legacy (.anki21), 3 = latest (.anki21b)
+    pub version: u32,
+    /// True if the `media` entry is a zstd-compressed protobuf list of media
+    /// entries rather than a JSON object
+    pub media_is_blob: bool,
 }
 
 /// Anki archive wrapper for ZIP file access
 pub struct AnkiArchive {
     archive: ZipArchive<Cursor<Vec<u8>>>,
     format: AnkiFormat,
+    /// Zip entry name of the collection database (may be nested in a folder)
+    db_entry: String,
+    /// Folder prefix of the media mapping and numbered media entries ("" at the root)
+    media_prefix: String,
+    /// Password for encrypted entries (ZipCrypto or AES)
+    password: Option<Vec<u8>>,
+    /// Reused buffer for compressed database bytes (see `extract_database_into`)
+    scratch: Vec<u8>,
+    /// Largest decompressed database size to accept
+    max_database_bytes: u64,
 }
 
 impl AnkiArchive {
     /// Open an Anki archive from a file path
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, AnkiError> {
-        let path = path.as_ref();
-
-        if !path.exists() {
-            return Err(AnkiError::FileNotFound(path.display().to_string()));
-        }
-
-        // Read entire file into memory for random access
-        let mut file = File::open(path)?;
-        let mut data = Vec::new();
-        file.read_to_end(&mut data)?;
+        Self::from_bytes(read_file(path.as_ref())?)
+    }
 
-        Self::from_bytes(data)
+    /// Open a password-protected Anki archive from a file path
+    ///
+    /// Reading an entry fails with `AnkiError::EncryptedArchive` if the
+    /// password is wrong. Unencrypted entries are read as usual.
+    pub fn open_with_password<P: AsRef<Path>>(path: P, password: &str) -> Result<Self, AnkiError> {
+        Self::from_bytes_with_password(read_file(path.as_ref())?, password)
     }
 
     /// Open an Anki archive from raw bytes
     pub fn from_bytes(data: Vec<u8>) -> Result<Self, AnkiError> {
         let cursor = Cursor::new(data);
-        let mut archive = ZipArchive::new(cursor)?;
+        let archive = ZipArchive::new(cursor)?;
 
         // Detect format by checking which database file exists
-        let format = Self::detect_format(&mut archive)?;
+        let (format, db_entry) = Self::detect_format(&archive)?;
+        let media_prefix = find_entry(&archive, "media")
+            .map(|entry| entry[..entry.len() - "media".len()].to_string())
+            .unwrap_or_default();
+
+        Ok(Self {
+            archive,
+            format,
+            db_entry,
+            media_prefix,
+            password: None,
+            scratch: Vec::new(),
+            max_database_bytes: DEFAULT_MAX_DATABASE_BYTES,
+        })
+    }
 
-        Ok(Self { archive, format })
+    /// Open a password-protected Anki archive from raw bytes
+    pub fn from_bytes_with_password(data: Vec<u8>, password: &str) -> Result<Self, AnkiError> {
+        let mut archive = Self::from_bytes(data)?;
+        archive.password = Some(password.as_bytes().to_vec());
+        Ok(archive)
     }
 
     /// Detect the Anki format by checking for database files
-    fn detect_format(archive: &mut ZipArchive<Cursor<Vec<u8>>>) -> Result<AnkiFormat, AnkiError> {
+    /// Collections nested inside a folder (e.g. "deck/collection.anki21") are found too
+    fn detect_format(
+        archive: &ZipArchive<Cursor<Vec<u8>>>,
+    ) -> Result<(AnkiFormat, String), AnkiError> {
         // Check in order of preference (newest format first)
-        if archive.by_name("collection.anki21b").is_ok() {
-            Ok(AnkiFormat::Compressed)
-        } else if archive.by_name("collection.anki21").is_ok() {
-            Ok(AnkiFormat::Modern)
-        } else if archive.by_name("collection.anki2").is_ok() {
-            Ok(AnkiFormat::Legacy)
-        } else {
-            Err(AnkiError::InvalidArchive)
-        }
+        [AnkiFormat::Compressed, AnkiFormat::Modern, AnkiFormat::Legacy]
+            .into_iter()
+            .find_map(|format| {
+                find_entry(archive, format.db_filename()).map(|entry| (format, entry))
+            })
+            .ok_or(AnkiError::InvalidArchive)
+    }
+
+    /// Set the largest decompressed database size to accept
+    ///
+    /// Extraction fails with `AnkiError::DecompressionError` once the database
+    /// grows past this, so a crafted archive cannot exhaust memory or disk.
+    /// Defaults to `DEFAULT_MAX_DATABASE_BYTES`.
+    pub fn set_max_database_bytes(&mut self, max: u64) {
+        self.max_database_bytes = max;
     }
 
     /// Get the detected format
@@ -93,65 +143,318 @@ impl AnkiArchive {
         self.format
     }
 
-    /// Extract and decompress the database
-    pub fn extract_database(&mut self) -> Result<Vec<u8>, AnkiError> {
-        let db_name = self.format.db_filename();
-        let format = self.format;
-
-        let data = {
-            let mut file = self.archive.by_name(db_name)?;
-            let mut data = Vec::with_capacity(file.size() as usize);
-            file.read_to_end(&mut data)?;
-            data
+    /// Whether the collection database entry is encrypted
+    ///
+    /// Encrypted archives need `open_with_password` / `from_bytes_with_password`.
+    pub fn is_encrypted(&mut self) -> bool {
+        self.archive
+            .index_for_name(&self.db_entry)
+            .and_then(|index| self.archive.by_index_raw(index).ok().map(|f| f.encrypted()))
+            .unwrap_or(false)
+    }
+
+    /// Look up an entry by name, decrypting it if a password was given
+    ///
+    /// Entries compressed with a method we cannot decompress fail with
+    /// `AnkiError::UnsupportedCompression` naming the entry and its method.
+    fn entry(&mut self, name: &str) -> Result<ZipFile<'_>, AnkiError> {
+        let method = self
+            .archive
+            .index_for_name(name)
+            .and_then(|index| self.archive.by_index_raw(index).ok().map(|f| f.compression()));
+
+        let result = match &self.password {
+            Some(password) => self.archive.by_name_decrypt(name, password),
+            None => self.archive.by_name(name),
         };
+        result.map_err(|e| match (AnkiError::from(e), method) {
+            (AnkiError::UnsupportedCompression(_), Some(method)) => {
+                AnkiError::UnsupportedCompression(format!("{} is compressed with {}", name, method))
+            }
+            (other, _) => other,
+        })
+    }
+
+    /// Whether the archive has an entry with this exact name
+    fn has_entry(&self, name: &str) -> bool {
+        self.archive.index_for_name(name).is_some()
+    }
+
+    /// List every collection database in the archive with its format
+    ///
+    /// Most archives hold a single collection, but multi-profile exports or
+    /// manually combined archives can contain several. Entries are returned
+    /// in archive order; pass a name to `extract_database_entry` to read one.
+    pub fn list_collections(&self) -> Vec<(String, AnkiFormat)> {
+        self.archive
+            .file_names()
+            .filter_map(|name| {
+                let filename = name.rsplit('/').next().unwrap_or(name);
+                AnkiFormat::from_db_filename(filename).map(|format| (name.to_string(), format))
+            })
+            .collect()
+    }
+
+    /// Extract and decompress the detected database
+    pub fn extract_database(&mut self) -> Result<Vec<u8>, AnkiError> {
+        let mut data = Vec::new();
+        self.extract_database_into(&mut data)?;
+        Ok(data)
+    }
+
+    /// Extract and decompress the detected database into `buf`
+    ///
+    /// `buf` is cleared first and its allocation reused, as is an internal
+    /// buffer for the compressed bytes, so extracting many archives with one
+    /// `AnkiArchive`/buffer pair avoids repeated large allocations.
+    pub fn extract_database_into(&mut self, buf: &mut Vec<u8>) -> Result<(), AnkiError> {
+        let entry = self.db_entry.clone();
+        self.extract_database_entry_into(&entry, buf)
+    }
+
+    /// Extract and decompress the collection database stored under `entry`
+    pub fn extract_database_entry(&mut self, entry: &str) -> Result<Vec<u8>, AnkiError> {
+        let mut data = Vec::new();
+        self.extract_database_entry_into(entry, &mut data)?;
+        Ok(data)
+    }
+
+    /// Extract and decompress the collection database stored under `entry` into `buf`
+    pub fn extract_database_entry_into(
+        &mut self,
+        entry: &str,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), AnkiError> {
+        let format = collection_format(entry)?;
+        buf.clear();
 
-        // Decompress if using compressed format
-        if format == AnkiFormat::Compressed {
-            decompress_zstd(&data)
-        } else {
-            Ok(data)
+        if format != AnkiFormat::Compressed {
+            let max = self.max_database_bytes;
+            let mut file = self.entry(entry)?;
+            buf.reserve(file.size().min(max) as usize);
+            return copy_limited(&mut file, buf, max);
         }
+
+        let max = self.max_database_bytes;
+        let mut compressed = std::mem::take(&mut self.scratch);
+        compressed.clear();
+        let result = (|| {
+            let mut file = self.entry(entry)?;
+            // The declared size is untrusted, so don't reserve past the cap
+            compressed.reserve(file.size().min(max) as usize);
+            file.read_to_end(&mut compressed)?;
+            drop(file);
+
+            // Stream-decode into `buf`; data without the zstd magic is stored as-is
+            if compressed.starts_with(&ZSTD_MAGIC) {
+                let decoder = zstd::stream::read::Decoder::new(&compressed[..])
+                    .map_err(|e| AnkiError::DecompressionError(e.to_string()))?;
+                copy_limited(decoder, buf, max)
+            } else {
+                copy_limited(&compressed[..], buf, max)
+            }
+        })();
+        self.scratch = compressed;
+        result
     }
 
-    /// Extract the media JSON mapping file
-    /// Returns a map of index (as string) -> filename
-    pub fn extract_media_mapping(&mut self) -> Result<HashMap<String, String>, AnkiError> {
-        match self.archive.by_name("media") {
-            Ok(mut file) => {
-                // Read as bytes first to handle potential encoding issues
-                let mut data = Vec::new();
-                file.read_to_end(&mut data)?;
-
-                // Handle empty content
-                if data.is_empty() {
-                    return Ok(HashMap::new());
-                }
+    /// Extract the database into a new file in `dir`, returning its path
+    ///
+    /// The entry is streamed straight to disk (decompressing on the fly for the
+    /// compressed format), so the database is never held in memory.
+    /// The caller owns the returned file.
+    pub fn extract_database_to_file(&mut self, dir: &Path) -> Result<PathBuf, AnkiError> {
+        let entry = self.db_entry.clone();
+        self.extract_database_entry_to_file(&entry, dir)
+    }
 
-                // Try to convert to string (lossy if needed)
-                let content = String::from_utf8_lossy(&data);
-                let content = content.trim();
+    /// Extract the collection database stored under `entry` into a new file in `dir`
+    pub fn extract_database_entry_to_file(
+        &mut self,
+        entry: &str,
+        dir: &Path,
+    ) -> Result<PathBuf, AnkiError> {
+        let format = collection_format(entry)?;
+        let path = unique_temp_path(dir);
 
-                if content.is_empty() {
-                    return Ok(HashMap::new());
-                }
+        let max = self.max_database_bytes;
 
-                // Parse JSON: {"0": "image.jpg", "1": "audio.mp3", ...}
-                match serde_json::from_str(content) {
-                    Ok(mapping) => Ok(mapping),
-                    Err(_) => {
-                        // Not valid JSON, might be binary format - return empty
-                        Ok(HashMap::new())
+        let result = (|| {
+            let mut file = self.entry(entry)?;
+            let mut out = BufWriter::new(File::create(&path)?);
+
+            if format == AnkiFormat::Compressed {
+                let decoder = zstd::stream::read::Decoder::new(&mut file)
+                    .map_err(|e| AnkiError::DecompressionError(e.to_string()))?;
+                copy_limited(decoder, &mut out, max)?;
+            } else {
+                copy_limited(&mut file, &mut out, max)?;
+            }
+
+            out.flush()?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => Ok(path),
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    /// Read the package metadata, if the archive has a `meta` entry
+    ///
+    /// Only exports from Anki 2.1.50+ include it; older archives return None
+    /// and always store the media mapping as JSON.
+    pub fn read_package_meta(&mut self) -> Option<PackageMeta> {
+        let meta_entry = format!("{}meta", self.media_prefix);
+        if !self.has_entry(&meta_entry) {
+            return None;
+        }
+
+        let mut data = Vec::new();
+        self.entry(&meta_entry).ok()?.read_to_end(&mut data).ok()?;
+
+        // message PackageMetadata { Version version = 1; }
+        let version = decode_protobuf(&data)?
+            .into_iter()
+            .find_map(|(field, value)| match (field, value) {
+                (1, ProtoValue::Varint(version)) => Some(version as u32),
+                _ => None,
+            })
+            .unwrap_or(0);
+
+        Some(PackageMeta { version, media_is_blob: version >= 3 })
+    }
+
+    /// Extract the media mapping of a latest-version (v3) package
+    ///
+    /// The `media` entry is a zstd-compressed `MediaEntries` protobuf; each
+    /// entry's position is its zip entry name unless it records another one.
+    /// Fails if the mapping decompresses to more than `max_bytes`.
+    /// Returns a map of index (as string) -> filename.
+    pub fn extract_media_entries(
+        &mut self,
+        max_bytes: u64,
+    ) -> Result<HashMap<String, String>, AnkiError> {
+        let media_entry = format!("{}media", self.media_prefix);
+        if !self.has_entry(&media_entry) {
+            return Ok(HashMap::new());
+        }
+
+        let mut data = Vec::new();
+        self.entry(&media_entry)?.read_to_end(&mut data)?;
+        if is_zstd_compressed(&data) {
+            data = decompress_zstd_limited(&data, max_bytes)?;
+        }
+
+        let invalid = || AnkiError::MediaError("invalid media entries".to_string());
+        let mut mapping = HashMap::new();
+
+        // message MediaEntries { repeated MediaEntry entries = 1; }
+        let entries = decode_protobuf(&data).ok_or_else(invalid)?;
+        let entries = entries.into_iter().filter(|(field, _)| *field == 1);
+        for (position, (_, value)) in entries.enumerate() {
+            let ProtoValue::Bytes(entry) = value else {
+                return Err(invalid());
+            };
+
+            // message MediaEntry { string name = 1; ...; uint32 legacy_zip_filename = 255; }
+            let mut name = None;
+            let mut index = position as u64;
+            for (field, value) in decode_protobuf(entry).ok_or_else(invalid)? {
+                match (field, value) {
+                    (1, ProtoValue::Bytes(bytes)) => {
+                        name = Some(String::from_utf8_lossy(bytes).into_owned())
                     }
+                    (255, ProtoValue::Varint(legacy)) => index = legacy,
+                    _ => {}
                 }
             }
-            Err(zip::result::ZipError::FileNotFound) => {
-                // No media file means no media
+
+            if let Some(name) = name {
+                mapping.insert(index.to_string(), name);
+            }
+        }
+
+        Ok(mapping)
+    }
+
+    /// Extract the media JSON mapping file
+    /// Returns a map of index (as string) -> filename
+    pub fn extract_media_mapping(&mut self) -> Result<HashMap<String, String>, AnkiError> {
+        let media_entry = format!("{}media", self.media_prefix);
+        if !self.has_entry(&media_entry) {
+            // No media file means no media
+            return Ok(HashMap::new());
+        }
+
+        let mut file = self.entry(&media_entry)?;
+        // Read as bytes first to handle potential encoding issues
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        // Handle empty content
+        if data.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        // Try to convert to string (lossy if needed)
+        let content = String::from_utf8_lossy(&data);
+        let content = content.trim();
+
+        if content.is_empty() {
+            return Ok(HashMap::new());
+        }
+
+        // Parse JSON: {"0": "image.jpg", "1": "audio.mp3", ...}
+        match serde_json::from_str(content) {
+            Ok(mapping) => Ok(mapping),
+            Err(_) => {
+                // Not valid JSON, might be binary format - return empty
                 Ok(HashMap::new())
             }
-            Err(e) => Err(e.into()),
         }
     }
 
+    /// List media mapping indices that have no zip entry behind them
+    ///
+    /// An empty list means every mapped file is present. Only the zip
+    /// directory is consulted, so this is cheap even for huge archives and
+    /// catches truncated downloads before a long import.
+    /// Indices are returned in numeric order. `max_bytes` caps the decompressed
+    /// size of a latest-version media mapping.
+    pub fn verify_media_integrity(&mut self, max_bytes: u64) -> Result<Vec<String>, AnkiError> {
+        let mapping = match self.read_package_meta() {
+            Some(meta) if meta.media_is_blob => self.extract_media_entries(max_bytes)?,
+            _ => self.extract_media_mapping()?,
+        };
+
+        let mut missing: Vec<String> = mapping
+            .into_keys()
+            .filter(|index| !self.has_entry(&format!("{}{}", self.media_prefix, index)))
+            .collect();
+        missing.sort_by_key(|index| (index.parse::<u64>().unwrap_or(u64::MAX), index.clone()));
+        Ok(missing)
+    }
+
+    /// Sum the stored (compressed) sizes of the given media entries
+    /// Reads only the zip directory; nothing is decompressed
+    pub fn media_compressed_size<'a, I>(&mut self, indices: I) -> u64
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        indices
+            .into_iter()
+            .filter_map(|index| {
+                let entry = format!("{}{}", self.media_prefix, index);
+                self.entry(&entry).ok().map(|f| f.compressed_size())
+            })
+            .sum()
+    }
+
     /// Get a list of all file names in the archive
     pub fn file_names(&self) -> Vec<String> {
         self.archive.file_names().map(|s| s.to_string()).collect()
@@ -159,26 +462,34 @@ impl AnkiArchive {
 
     /// Extract raw data for a file by index (as used in media mapping)
     pub fn extract_file_by_index(&mut self, index: &str) -> Result<Option<Vec<u8>>, AnkiError> {
-        match self.archive.by_name(index) {
-            Ok(mut file) => {
-                let mut data = Vec::with_capacity(file.size() as usize);
-                file.read_to_end(&mut data)?;
-                Ok(Some(data))
-            }
-            Err(zip::result::ZipError::FileNotFound) => Ok(None),
-            Err(e) => Err(e.into()),
+        // Media entries sit next to the mapping file
+        let entry = format!("{}{}", self.media_prefix, index);
+        if !self.has_entry(&entry) {
+            return Ok(None);
         }
+
+        let mut file = self.entry(&entry)?;
+        let mut data = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut data)?;
+        Ok(Some(data))
     }
 
     /// Extract media data and decompress if needed
-    pub fn extract_media(&mut self, index: &str) -> Result<Option<Vec<u8>>, AnkiError> {
+    /// Fails if the file decompresses to more than `max_bytes`
+    pub fn extract_media(
+        &mut self,
+        index: &str,
+        max_bytes: u64,
+    ) -> Result<Option<Vec<u8>>, AnkiError> {
         match self.extract_file_by_index(index)? {
-            Some(data) => {
-                // Decompress if zstd-compressed (decompress_zstd checks magic bytes)
-                let decompressed = decompress_zstd(&data)?;
-                Ok(Some(decompressed))
+            Some(data) if is_zstd_compressed(&data) => {
+                Ok(Some(decompress_zstd_limited(&data, max_bytes)?))
+            }
+            Some(data) if is_gzip_compressed(&data) => {
+                // Very old exports gzip some media entries
+                Ok(Some(decompress_gzip_limited(&data, max_bytes)?))
             }
-            None => Ok(None),
+            other => Ok(other),
         }
     }
 
@@ -193,16 +504,375 @@ impl AnkiArchive {
     }
 }
 
+/// A protobuf field value (fixed-width values are skipped)
+enum ProtoValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+/// Decode the top-level fields of a protobuf message as (field number, value) pairs
+/// Returns None if the message is malformed
+fn decode_protobuf(data: &[u8]) -> Option<Vec<(u64, ProtoValue<'_>)>> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let value = match key & 0x07 {
+            0 => ProtoValue::Varint(read_varint(data, &mut pos)?),
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|&end| end <= data.len())?;
+                let bytes = &data[pos..end];
+                pos = end;
+                ProtoValue::Bytes(bytes)
+            }
+            1 => {
+                pos += 8;
+                continue;
+            }
+            5 => {
+                pos += 4;
+                continue;
+            }
+            _ => return None,
+        };
+        fields.push((key >> 3, value));
+    }
+
+    (pos == data.len()).then_some(fields)
+}
+
+/// Copy `reader` into `writer`, failing once more than `max` bytes have been read
+fn copy_limited<R: Read, W: Write>(reader: R, writer: &mut W, max: u64) -> Result<(), AnkiError> {
+    // Copy one byte past the cap so overflowing data is detectable
+    let copied = io::copy(&mut reader.take(max.saturating_add(1)), writer)
+        .map_err(|e| AnkiError::DecompressionError(e.to_string()))?;
+
+    if copied > max {
+        return Err(AnkiError::DecompressionError(format!(
+            "collection database exceeds the limit of {} bytes",
+            max
+        )));
+    }
+    Ok(())
+}
+
+/// Read a whole archive file into memory for random access
+fn read_file(path: &Path) -> Result<Vec<u8>, AnkiError> {
+    if !path.exists() {
+        return Err(AnkiError::FileNotFound(path.display().to_string()));
+    }
+
+    let mut file = File::open(path)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Get the format of a collection entry from its filename
+fn collection_format(entry: &str) -> Result<AnkiFormat, AnkiError> {
+    let filename = entry.rsplit('/').next().unwrap_or(entry);
+    AnkiFormat::from_db_filename(filename).ok_or(AnkiError::InvalidArchive)
+}
+
+/// Find a zip entry by filename, either at the root or inside a folder
+/// Prefers the shallowest match when several exist
+fn find_entry(archive: &ZipArchive<Cursor<Vec<u8>>>, filename: &str) -> Option<String> {
+    let suffix = format!("/{}", filename);
+    archive
+        .file_names()
+        .filter(|name| *name == filename || name.ends_with(&suffix))
+        .min_by_key(|name| (name.matches('/').count(), name.len()))
+        .map(|name| name.to_string())
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
     #[test]
     fn test_zstd_magic_detection() {
-        let zstd_data = vec![0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x00];
+        let zstd_data = [0x28, 0xB5, 0x2F, 0xFD, 0x00, 0x00];
         assert_eq!(&zstd_data[0..4], &[0x28, 0xB5, 0x2F, 0xFD]);
 
-        let regular_data = vec![0x53, 0x51, 0x4C, 0x69]; // "SQLi"
+        let regular_data = [0x53, 0x51, 0x4C, 0x69]; // "SQLi"
         assert_ne!(&regular_data[0..4], &[0x28, 0xB5, 0x2F, 0xFD]);
     }
+
+    /// Build an in-memory zip from (entry name, contents) pairs, stored uncompressed
+    pub(crate) fn zip_bytes(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in entries {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn test_nested_collection() {
+        let data = zip_bytes(&[
+            ("deck/collection.anki2", b"legacy"),
+            ("deck/collection.anki21", b"modern"),
+            ("deck/media", br#"{"0": "a.mp3"}"#),
+            ("deck/0", b"audio"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+
+        // Newest format wins
+        assert_eq!(archive.format(), AnkiFormat::Modern);
+        assert_eq!(archive.extract_database().unwrap(), b"modern");
+        assert_eq!(archive.extract_media_mapping().unwrap()["0"], "a.mp3");
+        assert_eq!(archive.extract_media("0", 1024).unwrap().unwrap(), b"audio");
+    }
+
+    #[test]
+    fn test_gzip_media() {
+        use flate2::write::GzEncoder;
+
+        let png = b"\x89PNG\r\n\x1a\nimage data";
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(png).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let data = zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "a.png"}"#),
+            ("0", &compressed),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert_eq!(archive.extract_media("0", 1024).unwrap().unwrap(), png);
+    }
+
+    #[test]
+    fn test_media_decompression_limit() {
+        let audio = vec![0u8; 4096];
+        let compressed = zstd::encode_all(&audio[..], 0).unwrap();
+        let data = zip_bytes(&[
+            ("collection.anki21b", b"db"),
+            ("meta", &[0x08, 0x03]),
+            ("media", &compressed),
+            ("0", &compressed),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+
+        assert_eq!(archive.extract_media("0", 4096).unwrap().unwrap(), audio);
+        assert!(matches!(
+            archive.extract_media("0", 1000),
+            Err(AnkiError::DecompressionError(_))
+        ));
+        assert!(matches!(
+            archive.extract_media_entries(1000),
+            Err(AnkiError::DecompressionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_multiple_collections() {
+        let data = zip_bytes(&[
+            ("alice/collection.anki21", b"alice"),
+            ("bob/collection.anki2", b"bob"),
+            ("notes.txt", b"hello"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+
+        assert_eq!(
+            archive.list_collections(),
+            vec![
+                ("alice/collection.anki21".to_string(), AnkiFormat::Modern),
+                ("bob/collection.anki2".to_string(), AnkiFormat::Legacy),
+            ]
+        );
+        assert_eq!(archive.extract_database_entry("bob/collection.anki2").unwrap(), b"bob");
+        assert!(matches!(
+            archive.extract_database_entry("notes.txt"),
+            Err(AnkiError::InvalidArchive)
+        ));
+    }
+
+    #[test]
+    fn test_encrypted_archive() {
+        let options = zip::write::SimpleFileOptions::default()
+            .with_aes_encryption(zip::AesMode::Aes256, "secret");
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("collection.anki2", options).unwrap();
+        zip.write_all(b"db").unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let mut archive = AnkiArchive::from_bytes(data.clone()).unwrap();
+        assert!(archive.is_encrypted());
+        assert!(matches!(archive.extract_database(), Err(AnkiError::EncryptedArchive)));
+
+        let mut archive = AnkiArchive::from_bytes_with_password(data.clone(), "wrong").unwrap();
+        assert!(matches!(archive.extract_database(), Err(AnkiError::EncryptedArchive)));
+
+        let mut archive = AnkiArchive::from_bytes_with_password(data, "secret").unwrap();
+        assert_eq!(archive.extract_database().unwrap(), b"db");
+
+        // A password is harmless for unencrypted archives
+        let plain = zip_bytes(&[("collection.anki2", b"db")]);
+        let mut archive = AnkiArchive::from_bytes_with_password(plain, "secret").unwrap();
+        assert!(!archive.is_encrypted());
+        assert_eq!(archive.extract_database().unwrap(), b"db");
+    }
+
+    #[test]
+    fn test_bzip2_entry() {
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Bzip2);
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        zip.start_file("collection.anki21", options).unwrap();
+        zip.write_all(&b"modern".repeat(100)).unwrap();
+        let data = zip.finish().unwrap().into_inner();
+
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert_eq!(archive.extract_database().unwrap(), b"modern".repeat(100));
+    }
+
+    #[test]
+    fn test_unsupported_compression() {
+        let mut data = zip_bytes(&[("collection.anki2", b"db")]);
+        // Relabel the entry as XZ (method 95), which is not enabled, in both
+        // the local file header and the central directory
+        for (signature, offset) in [(b"PK\x03\x04", 8), (b"PK\x01\x02", 10)] {
+            let start = data.windows(4).position(|w| w == signature).unwrap();
+            data[start + offset..start + offset + 2].copy_from_slice(&95u16.to_le_bytes());
+        }
+
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        match archive.extract_database() {
+            Err(AnkiError::UnsupportedCompression(message)) => {
+                assert!(message.contains("collection.anki2"), "{}", message);
+                assert!(message.contains("95"), "{}", message);
+            }
+            other => panic!("expected UnsupportedCompression, got {:?}", other.map(|d| d.len())),
+        }
+    }
+
+    /// Encode a length-delimited protobuf field
+    fn proto_bytes(field: u8, bytes: &[u8]) -> Vec<u8> {
+        let mut out = vec![(field << 3) | 2, bytes.len() as u8];
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    #[test]
+    fn test_package_meta_v3_media() {
+        let mut entries = proto_bytes(1, &proto_bytes(1, b"a.mp3"));
+        entries.extend(proto_bytes(1, &proto_bytes(1, "b\u{e9}.png".as_bytes())));
+        let media = zstd::encode_all(&entries[..], 0).unwrap();
+
+        let data = zip_bytes(&[
+            ("collection.anki21b", b"db"),
+            ("meta", &[0x08, 0x03]),
+            ("media", &media),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+
+        assert_eq!(
+            archive.read_package_meta(),
+            Some(PackageMeta { version: 3, media_is_blob: true })
+        );
+        let mapping = archive.extract_media_entries(1024).unwrap();
+        assert_eq!(mapping.len(), 2);
+        assert_eq!(mapping["0"], "a.mp3");
+        assert_eq!(mapping["1"], "b\u{e9}.png");
+    }
+
+    #[test]
+    fn test_package_meta_legacy() {
+        let data = zip_bytes(&[("collection.anki2", b"db"), ("media", b"{}")]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert_eq!(archive.read_package_meta(), None);
+
+        let data = zip_bytes(&[("collection.anki21", b"db"), ("meta", &[0x08, 0x02])]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert_eq!(
+            archive.read_package_meta(),
+            Some(PackageMeta { version: 2, media_is_blob: false })
+        );
+    }
+
+    #[test]
+    fn test_verify_media_integrity() {
+        let data = zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "a.mp3", "1": "b.png", "2": "c.png", "10": "d.png"}"#),
+            ("1", b"png"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert_eq!(archive.verify_media_integrity(1024).unwrap(), vec!["0", "2", "10"]);
+
+        let data = zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "a.mp3"}"#),
+            ("0", b"mp3"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        assert!(archive.verify_media_integrity(1024).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_extract_database_into() {
+        let compressed = zstd::encode_all(&b"sqlite".repeat(100)[..], 0).unwrap();
+        let data = zip_bytes(&[("collection.anki21b", &compressed)]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+
+        let mut buf = b"stale".to_vec();
+        archive.extract_database_into(&mut buf).unwrap();
+        assert_eq!(buf, b"sqlite".repeat(100));
+
+        // Extracting again reuses the buffer instead of appending
+        let capacity = buf.capacity();
+        archive.extract_database_into(&mut buf).unwrap();
+        assert_eq!(buf, b"sqlite".repeat(100));
+        assert_eq!(buf.capacity(), capacity);
+        assert_eq!(archive.extract_database().unwrap(), buf);
+    }
+
+    #[test]
+    fn test_max_database_bytes() {
+        let compressed = zstd::encode_all(&vec![0u8; 1024 * 1024][..], 0).unwrap();
+        let data = zip_bytes(&[("collection.anki21b", &compressed)]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        archive.set_max_database_bytes(1000);
+
+        assert!(matches!(
+            archive.extract_database(),
+            Err(AnkiError::DecompressionError(_))
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            archive.extract_database_to_file(dir.path()),
+            Err(AnkiError::DecompressionError(_))
+        ));
+        // The partial file is cleaned up
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        archive.set_max_database_bytes(1024 * 1024);
+        assert_eq!(archive.extract_database().unwrap().len(), 1024 * 1024);
+    }
+
+    #[test]
+    fn test_missing_collection() {
+        let data = zip_bytes(&[("notes.txt", b"hello")]);
+        assert!(matches!(AnkiArchive::from_bytes(data), Err(AnkiError::InvalidArchive)));
+    }
+
+    #[test]
+    fn test_media_compressed_size() {
+        let data = zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "a.mp3", "1": "b.png", "2": "missing.png"}"#),
+            ("0", b"12345"),
+            ("1", b"123"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        let mapping = archive.extract_media_mapping().unwrap();
+        // Entries are stored uncompressed, so compressed size equals length
+        assert_eq!(archive.media_compressed_size(mapping.keys()), 8);
+    }
 }
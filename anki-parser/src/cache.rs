@@ -0,0 +1,101 @@
+//! Process-wide cache of parsed collections, keyed by `collection_fingerprint`
+//!
+//! Only `parse_anki_file` uses it, since other parse options change the result.
+//! Each entry keeps a whole collection alive, media bytes included, so memory
+//! grows with the capacity times the size of the largest decks parsed. A hit
+//! returns a copy of the cards but shares the `AnkiMediaStore`, so media
+//! added to or removed from one copy shows up in the cached entry too.
+
+use std::collections::VecDeque;
+use std::sync::{LazyLock, Mutex};
+
+use crate::models::AnkiCollection;
+
+/// Number of collections kept until `set_capacity` is called
+pub const DEFAULT_CAPACITY: usize = 4;
+
+/// Least recently used entries are evicted first
+struct ParseCache {
+    capacity: usize,
+    /// (fingerprint, collection), most recently used first
+    entries: VecDeque<(String, AnkiCollection)>,
+}
+
+static CACHE: LazyLock<Mutex<ParseCache>> = LazyLock::new(|| {
+    Mutex::new(ParseCache {
+        capacity: DEFAULT_CAPACITY,
+        entries: VecDeque::new(),
+    })
+});
+
+/// A copy of the collection cached under `fingerprint`, marking it as recently used
+pub fn get(fingerprint: &str) -> Option<AnkiCollection> {
+    let mut cache = CACHE.lock().unwrap();
+    let index = cache.entries.iter().position(|(key, _)| key == fingerprint)?;
+    let entry = cache.entries.remove(index)?;
+    let collection = entry.1.clone();
+    cache.entries.push_front(entry);
+    Some(collection)
+}
+
+/// Cache a copy of `collection`, evicting the least recently used entry if full
+pub fn insert(fingerprint: String, collection: &AnkiCollection) {
+    let mut cache = CACHE.lock().unwrap();
+    if cache.capacity == 0 {
+        return;
+    }
+    cache.entries.retain(|(key, _)| *key != fingerprint);
+    cache.entries.push_front((fingerprint, collection.clone()));
+    let capacity = cache.capacity;
+    cache.entries.truncate(capacity);
+}
+
+/// Drop every cached collection
+pub fn clear() {
+    CACHE.lock().unwrap().entries.clear();
+}
+
+/// Keep at most `capacity` collections, evicting the least recently used
+/// ones now if there are more. Zero disables caching
+pub fn set_capacity(capacity: usize) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.capacity = capacity;
+    cache.entries.truncate(capacity);
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::models::AnkiDeck;
+    use std::collections::HashMap;
+
+    fn collection(deck_count: u32) -> AnkiCollection {
+        let decks = (0..deck_count as i64)
+            .map(|id| AnkiDeck::from_name(id, format!("Deck {}", id)))
+            .collect();
+        crate::models::tests::collection(decks, HashMap::new())
+    }
+
+    /// Held by tests that use the process-wide cache, so they don't evict
+    /// each other's entries
+    pub(crate) static CACHE_TEST_LOCK: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_lru_eviction() {
+        let _guard = CACHE_TEST_LOCK.lock().unwrap();
+        set_capacity(2);
+        insert("lru-a".to_string(), &collection(1));
+        insert("lru-b".to_string(), &collection(2));
+        // Touching "a" makes "b" the least recently used
+        assert_eq!(get("lru-a").unwrap().decks.len(), 1);
+        insert("lru-c".to_string(), &collection(3));
+
+        assert!(get("lru-b").is_none());
+        assert!(get("lru-a").is_some());
+        assert_eq!(get("lru-c").unwrap().decks.len(), 3);
+
+        clear();
+        assert!(get("lru-a").is_none());
+        set_capacity(DEFAULT_CAPACITY);
+    }
+}
@@ -1,70 +1,208 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::LazyLock;
+use std::time::Instant;
 
 use regex::Regex;
-use rusqlite::{Connection, OpenFlags};
+use rusqlite::serialize::OwnedData;
+use rusqlite::types::ValueRef;
+use rusqlite::{ffi, Connection, DatabaseName, OpenFlags};
 use serde_json::Value;
 
-use crate::error::AnkiError;
-use crate::models::{AnkiCard, AnkiDeck};
+use crate::error::{check_deadline, AnkiError, DatabaseContext};
+use crate::html::{
+    clean_html_with, compile_sound_patterns, decode_html_entities, process_card_fields_with,
+    unescape_sound_filename, HtmlOptions, IMG_SRC_REGEX, SOUND_REGEX,
+};
+use crate::models::{
+    AnkiCard, AnkiCardTemplate, AnkiDeck, AnkiNoteType, CollectionMeta, DeckSortMode,
+    GraveEntry, ParseEvent, ParseOptions, TagInfo,
+};
+use crate::template;
 
 /// Batch size for processing cards (for progress reporting)
 const BATCH_SIZE: usize = 1000;
 
+/// Operation names used as context in database error messages
+const READING_DECKS: &str = "reading decks";
+const READING_NOTE_TYPES: &str = "reading note types";
+const READING_COLLECTION_META: &str = "reading collection metadata";
+const READING_CARDS: &str = "reading cards";
+const READING_TAGS: &str = "reading tags";
+const READING_GRAVES: &str = "reading graves";
+
+/// Newest collection schema version (`col.ver`) this parser understands
+pub const MAX_SCHEMA_VERSION: u32 = 18;
+
+/// Card type (`cards.type`) of cards that have never been studied
+const CARD_TYPE_NEW: u8 = 0;
+
+/// Card queue values (`cards.queue`) for cards hidden from study
+const QUEUE_SUSPENDED: i32 = -1;
+const QUEUE_SCHED_BURIED: i32 = -2;
+const QUEUE_USER_BURIED: i32 = -3;
+
+/// Magic string at the start of every SQLite database file
+const SQLITE_HEADER: &[u8; 16] = b"SQLite format 3\0";
+
+/// Header bytes holding the file format write/read versions (2 = WAL)
+const WAL_VERSION_OFFSETS: std::ops::Range<usize> = 18..20;
+
+/// Counter for unique temp file names within a process
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
 /// Anki database wrapper
 pub struct AnkiDatabase {
     conn: Connection,
     _temp_path: Option<PathBuf>,
+    /// Note types keyed by ID, parsed on the first card read and reused after
+    note_types: OnceCell<HashMap<i64, AnkiNoteType>>,
 }
 
 impl AnkiDatabase {
     /// Open a database from raw bytes
-    /// Creates a temporary file since rusqlite needs a file path
+    /// Creates a temporary file since rusqlite needs a file path, falling back
+    /// to an in-memory copy if the temp dir isn't writable
     pub fn open_from_bytes(data: &[u8]) -> Result<Self, AnkiError> {
+        Self::open_from_bytes_in(data, &std::env::temp_dir())
+    }
+
+    /// `open_from_bytes`, with the temp file created in `dir`
+    fn open_from_bytes_in(data: &[u8], dir: &Path) -> Result<Self, AnkiError> {
+        check_sqlite_header(data)?;
+
         // Create a temp file for the database
-        let temp_dir = std::env::temp_dir();
-        let temp_path = temp_dir.join(format!("anki_import_{}.db", std::process::id()));
+        let temp_path = unique_temp_path(dir);
+
+        if let Err(write_error) = std::fs::write(&temp_path, data) {
+            let _ = std::fs::remove_file(&temp_path);
+            log::warn!("Can't write temp database ({}), loading it into memory", write_error);
+            let conn = Self::deserialize(data).map_err(|e| {
+                AnkiError::DatabaseError(format!(
+                    "writing temp file failed ({}) and loading into memory failed ({})",
+                    write_error, e
+                ))
+            })?;
+            return Ok(Self {
+                conn,
+                _temp_path: None,
+                note_types: OnceCell::new(),
+            });
+        }
 
-        std::fs::write(&temp_path, data)?;
+        Self::open_owned_path(temp_path)
+    }
 
-        let conn = Connection::open_with_flags(
-            &temp_path,
-            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
-        )?;
+    /// Load a database image into a read-only in-memory connection
+    fn deserialize(data: &[u8]) -> rusqlite::Result<Connection> {
+        let mut conn = Connection::open_in_memory()?;
+
+        // SQLite frees the image when the connection closes, so it has to own
+        // a copy allocated with its own allocator
+        // SAFETY: the copy stays within the `data.len()` bytes just allocated
+        let image = unsafe {
+            let ptr = ffi::sqlite3_malloc64(data.len() as u64) as *mut u8;
+            let ptr = NonNull::new(ptr).ok_or_else(|| {
+                rusqlite::Error::SqliteFailure(ffi::Error::new(ffi::SQLITE_NOMEM), None)
+            })?;
+            std::ptr::copy_nonoverlapping(data.as_ptr(), ptr.as_ptr(), data.len());
+            // In-memory databases can't use a WAL, so mark the image as rollback-journal
+            if data.len() > WAL_VERSION_OFFSETS.end {
+                for offset in WAL_VERSION_OFFSETS {
+                    *ptr.as_ptr().add(offset) = 1;
+                }
+            }
+            OwnedData::from_raw_nonnull(ptr, data.len())
+        };
+
+        conn.deserialize(DatabaseName::Main, image, true)?;
+        Ok(conn)
+    }
 
+    /// Open an existing SQLite file directly (read-only), without copying it
+    pub fn open_path(path: &Path) -> Result<Self, AnkiError> {
         Ok(Self {
-            conn,
-            _temp_path: Some(temp_path),
+            conn: Self::connect(path)?,
+            _temp_path: None,
+            note_types: OnceCell::new(),
         })
     }
 
+    /// Open a temporary SQLite file, taking ownership of it
+    /// The file is deleted when the database is dropped (or if opening fails)
+    pub fn open_owned_path(path: PathBuf) -> Result<Self, AnkiError> {
+        match Self::connect(&path) {
+            Ok(conn) => Ok(Self {
+                conn,
+                _temp_path: Some(path),
+                note_types: OnceCell::new(),
+            }),
+            Err(e) => {
+                let _ = std::fs::remove_file(&path);
+                Err(e)
+            }
+        }
+    }
+
+    fn connect(path: &Path) -> Result<Connection, AnkiError> {
+        let mut header = Vec::with_capacity(SQLITE_HEADER.len());
+        std::fs::File::open(path)?
+            .take(SQLITE_HEADER.len() as u64)
+            .read_to_end(&mut header)?;
+        check_sqlite_header(&header)?;
+
+        Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )
+        .context("opening database")
+    }
+
     /// Parse all decks from the database
-    pub fn parse_decks(&self) -> Result<Vec<AnkiDeck>, AnkiError> {
+    /// Parents always come before their children; `sort_mode` orders decks within a level
+    pub fn parse_decks(&self, sort_mode: DeckSortMode) -> Result<Vec<AnkiDeck>, AnkiError> {
+        self.parse_decks_with_separator(sort_mode, None)
+    }
+
+    /// Parse all decks, first rewriting `separator` in deck names to "::"
+    /// For third-party exports that nest decks with "/" or ">" instead
+    pub fn parse_decks_with_separator(
+        &self,
+        sort_mode: DeckSortMode,
+        separator: Option<&str>,
+    ) -> Result<Vec<AnkiDeck>, AnkiError> {
         // Try modern schema first (Anki 2.1.50+) - decks table with blob data
-        if let Ok(decks) = self.parse_decks_modern() {
-            if !decks.is_empty() {
-                return Ok(decks);
-            }
+        let mut decks = match self.parse_decks_modern(separator) {
+            Ok(decks) if !decks.is_empty() => decks,
+            // Fall back to legacy schema - JSON in col table. Hybrid collections
+            // can ship an empty modern table alongside populated legacy JSON
+            _ => self.parse_decks_legacy(separator)?,
+        };
+
+        // Anki stores no explicit display order, but deck IDs are creation
+        // timestamps, so number decks in the order they were created
+        decks.sort_by_key(|d| d.id);
+        for (i, deck) in decks.iter_mut().enumerate() {
+            deck.order = i as u32;
         }
 
-        // Fall back to legacy schema - JSON in col table
-        self.parse_decks_legacy()
+        sort_decks(&mut decks, sort_mode);
+
+        Ok(decks)
     }
 
     /// Parse decks from modern schema (Anki 2.1.50+)
     /// In newer versions, decks are stored in a separate 'decks' table
     /// The 'name' column may be text or binary (protobuf)
-    fn parse_decks_modern(&self) -> Result<Vec<AnkiDeck>, AnkiError> {
+    fn parse_decks_modern(&self, separator: Option<&str>) -> Result<Vec<AnkiDeck>, AnkiError> {
         let mut decks = Vec::new();
 
         // Check if decks table exists
-        let table_exists: bool = self.conn.query_row(
-            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name='decks'",
-            [],
-            |row| row.get(0),
-        ).unwrap_or(false);
-
-        if !table_exists {
+        if !self.table_exists("decks") {
             return Ok(decks);
         }
 
@@ -72,22 +210,11 @@ impl AnkiDatabase {
         // Both id and name might be stored as blobs in some versions
         let mut stmt = self.conn.prepare(
             "SELECT id, name FROM decks"
-        )?;
+        ).context(READING_DECKS)?;
 
         let rows = stmt.query_map([], |row| {
             // Handle id - might be integer or blob
-            let id: i64 = match row.get_ref(0)? {
-                rusqlite::types::ValueRef::Integer(i) => i,
-                rusqlite::types::ValueRef::Blob(bytes) => {
-                    // Try to parse as little-endian i64
-                    if bytes.len() >= 8 {
-                        i64::from_le_bytes(bytes[..8].try_into().unwrap_or([0; 8]))
-                    } else {
-                        0
-                    }
-                }
-                _ => 0,
-            };
+            let id = value_to_i64(row.get_ref(0)?);
 
             // Handle name - might be text or blob (protobuf)
             let name_bytes: Vec<u8> = match row.get_ref(1)? {
@@ -97,10 +224,10 @@ impl AnkiDatabase {
             };
 
             Ok((id, name_bytes))
-        })?;
+        }).context(READING_DECKS)?;
 
         for row_result in rows {
-            let (id, name_bytes) = row_result?;
+            let (id, name_bytes) = row_result.context(READING_DECKS)?;
 
             if id == 0 {
                 continue;
@@ -117,23 +244,16 @@ impl AnkiDatabase {
             };
 
             if !name.is_empty() {
-                decks.push(AnkiDeck::from_name(id, name));
+                decks.push(AnkiDeck::from_name(id, canonical_deck_name(name, separator)));
             }
         }
 
-        // Sort by hierarchy depth
-        decks.sort_by(|a, b| {
-            let a_depth = a.name.matches("::").count();
-            let b_depth = b.name.matches("::").count();
-            a_depth.cmp(&b_depth).then_with(|| a.name.cmp(&b.name))
-        });
-
         Ok(decks)
     }
 
     /// Parse decks from legacy schema (pre-2.1.50)
     /// Decks stored as JSON in the 'col' table
-    fn parse_decks_legacy(&self) -> Result<Vec<AnkiDeck>, AnkiError> {
+    fn parse_decks_legacy(&self, separator: Option<&str>) -> Result<Vec<AnkiDeck>, AnkiError> {
         // Decks are stored as JSON in the `col` table
         let decks_json: Option<String> = self.conn.query_row(
             "SELECT decks FROM col",
@@ -156,8 +276,8 @@ impl AnkiDatabase {
             for (id_str, deck_value) in decks_map {
                 let id: i64 = id_str.parse().unwrap_or(0);
 
-                // Skip the default deck with ID 1 if it has no cards
-                // (Anki always has a "Default" deck)
+                // The default deck with ID 1 is kept here; parse_anki_file
+                // drops it once it knows whether it has any cards
 
                 let name = deck_value["name"]
                     .as_str()
@@ -169,34 +289,399 @@ impl AnkiDatabase {
                     continue;
                 }
 
-                decks.push(AnkiDeck::from_name(id, name));
+                decks.push(AnkiDeck::from_name(id, canonical_deck_name(name, separator)));
             }
         }
 
-        // Sort by hierarchy depth (parents before children)
-        decks.sort_by(|a, b| {
-            let a_depth = a.name.matches("::").count();
-            let b_depth = b.name.matches("::").count();
-            a_depth.cmp(&b_depth).then_with(|| a.name.cmp(&b.name))
-        });
-
         Ok(decks)
     }
 
+    /// Parse all note types (models) from the database
+    pub fn parse_note_types(&self) -> Result<Vec<AnkiNoteType>, AnkiError> {
+        // Try modern schema first (Anki 2.1.50+) - notetypes/fields/templates tables
+        if let Ok(note_types) = self.parse_note_types_modern() {
+            if !note_types.is_empty() {
+                return Ok(note_types);
+            }
+        }
+
+        // Fall back to legacy schema - JSON in col table
+        self.parse_note_types_legacy()
+    }
+
+    /// Note types ordered by ID, from the same cache card parsing uses
+    /// Empty if they can't be read
+    pub fn note_types(&self) -> Vec<AnkiNoteType> {
+        let mut note_types: Vec<AnkiNoteType> = self.note_types_by_id().values().cloned().collect();
+        note_types.sort_by_key(|nt| nt.id);
+        note_types
+    }
+
+    /// Note types keyed by ID, parsed once per database
+    /// Empty if they can't be read
+    fn note_types_by_id(&self) -> &HashMap<i64, AnkiNoteType> {
+        self.note_types.get_or_init(|| {
+            self.parse_note_types()
+                .unwrap_or_default()
+                .into_iter()
+                .map(|nt| (nt.id, nt))
+                .collect()
+        })
+    }
+
+    /// Parse note types from modern schema (Anki 2.1.50+)
+    /// Field and template rows live in their own tables; template formats
+    /// are stored in a protobuf config blob
+    fn parse_note_types_modern(&self) -> Result<Vec<AnkiNoteType>, AnkiError> {
+        if !self.table_exists("notetypes") {
+            return Ok(Vec::new());
+        }
+
+        let mut note_types: Vec<AnkiNoteType> = self
+            .conn
+            .prepare("SELECT id, name, config FROM notetypes ORDER BY id")
+            .context(READING_NOTE_TYPES)?
+            .query_map([], |row| {
+                // Notetype config: field 1 = kind (0 = normal, 1 = cloze), field 3 = css
+                let (is_cloze, css) = match row.get_ref(2)? {
+                    ValueRef::Blob(config) => (
+                        protobuf_varint_field(config, 1) == Some(1),
+                        protobuf_string_field(config, 3).unwrap_or_default(),
+                    ),
+                    _ => (false, String::new()),
+                };
+                Ok(AnkiNoteType {
+                    id: row.get(0)?,
+                    name: value_to_string(row.get_ref(1)?),
+                    field_names: Vec::new(),
+                    templates: Vec::new(),
+                    css,
+                    is_cloze,
+                })
+            })
+            .context(READING_NOTE_TYPES)?
+            .collect::<Result<_, _>>()
+            .context(READING_NOTE_TYPES)?;
+
+        let index: HashMap<i64, usize> = note_types
+            .iter()
+            .enumerate()
+            .map(|(i, nt)| (nt.id, i))
+            .collect();
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT ntid, name FROM fields ORDER BY ntid, ord")
+            .context(READING_NOTE_TYPES)?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, value_to_string(row.get_ref(1)?)))
+        }).context(READING_NOTE_TYPES)?;
+        for row_result in rows {
+            let (ntid, name) = row_result.context(READING_NOTE_TYPES)?;
+            if let Some(&i) = index.get(&ntid) {
+                note_types[i].field_names.push(name);
+            }
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT ntid, ord, name, config FROM templates ORDER BY ntid, ord")
+            .context(READING_NOTE_TYPES)?;
+        let rows = stmt.query_map([], |row| {
+            let config: Vec<u8> = match row.get_ref(3)? {
+                ValueRef::Blob(bytes) | ValueRef::Text(bytes) => bytes.to_vec(),
+                _ => Vec::new(),
+            };
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, u32>(1)?,
+                value_to_string(row.get_ref(2)?),
+                config,
+            ))
+        }).context(READING_NOTE_TYPES)?;
+        for row_result in rows {
+            let (ntid, ordinal, name, config) = row_result.context(READING_NOTE_TYPES)?;
+            if let Some(&i) = index.get(&ntid) {
+                // Template config: field 1 = q_format, field 2 = a_format
+                note_types[i].templates.push(AnkiCardTemplate {
+                    name,
+                    ordinal,
+                    question_format: protobuf_string_field(&config, 1).unwrap_or_default(),
+                    answer_format: protobuf_string_field(&config, 2).unwrap_or_default(),
+                });
+            }
+        }
+
+        Ok(note_types)
+    }
+
+    /// Parse note types from legacy schema (pre-2.1.50)
+    /// Note types stored as JSON in the 'models' column of the 'col' table
+    fn parse_note_types_legacy(&self) -> Result<Vec<AnkiNoteType>, AnkiError> {
+        let models_json: Option<String> = self.conn.query_row(
+            "SELECT models FROM col",
+            [],
+            |row| row.get(0),
+        ).ok();
+
+        let models_json = match models_json {
+            Some(json) if !json.trim().is_empty() => json,
+            _ => return Ok(Vec::new()),
+        };
+
+        let models_value: Value = serde_json::from_str(&models_json)?;
+        let mut note_types = Vec::new();
+
+        if let Value::Object(models_map) = models_value {
+            for (id_str, model) in models_map {
+                let id: i64 = id_str.parse().unwrap_or(0);
+                let name = model["name"].as_str().unwrap_or("").to_string();
+
+                let mut fields: Vec<(u64, String)> = model["flds"]
+                    .as_array()
+                    .map(|flds| {
+                        flds.iter()
+                            .enumerate()
+                            .map(|(i, f)| {
+                                let ord = f["ord"].as_u64().unwrap_or(i as u64);
+                                (ord, f["name"].as_str().unwrap_or("").to_string())
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                fields.sort_by_key(|(ord, _)| *ord);
+
+                let mut templates: Vec<AnkiCardTemplate> = model["tmpls"]
+                    .as_array()
+                    .map(|tmpls| {
+                        tmpls
+                            .iter()
+                            .enumerate()
+                            .map(|(i, t)| AnkiCardTemplate {
+                                name: t["name"].as_str().unwrap_or("").to_string(),
+                                ordinal: t["ord"].as_u64().unwrap_or(i as u64) as u32,
+                                question_format: t["qfmt"].as_str().unwrap_or("").to_string(),
+                                answer_format: t["afmt"].as_str().unwrap_or("").to_string(),
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                templates.sort_by_key(|t| t.ordinal);
+
+                note_types.push(AnkiNoteType {
+                    id,
+                    name,
+                    field_names: fields.into_iter().map(|(_, name)| name).collect(),
+                    templates,
+                    css: model["css"].as_str().unwrap_or("").to_string(),
+                    // "type": 0 = standard, 1 = cloze
+                    is_cloze: model["type"].as_i64() == Some(1),
+                });
+            }
+        }
+
+        note_types.sort_by_key(|nt| nt.id);
+
+        Ok(note_types)
+    }
+
+    /// Parse the registered tags from the `tags` table (Anki 2.1.41+)
+    /// Returns an empty list on legacy collections, which have no such table.
+    /// Tables without the `collapsed` column report every tag as expanded
+    pub fn parse_all_tags(&self) -> Result<Vec<TagInfo>, AnkiError> {
+        if !self.table_exists("tags") {
+            return Ok(Vec::new());
+        }
+
+        let has_collapsed: bool = self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('tags') WHERE name = 'collapsed'",
+            [],
+            |row| row.get(0),
+        ).context(READING_TAGS)?;
+        // Sorted in Rust: Anki declares `tag` with its own `unicase`
+        // collation, which this connection does not register
+        let sql = if has_collapsed {
+            "SELECT tag, collapsed FROM tags"
+        } else {
+            "SELECT tag, 0 FROM tags"
+        };
+
+        let mut stmt = self.conn.prepare(sql).context(READING_TAGS)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(TagInfo {
+                name: row.get(0)?,
+                expanded: !row.get::<_, bool>(1)?,
+            })
+        }).context(READING_TAGS)?;
+
+        let mut tags = rows.collect::<Result<Vec<_>, _>>().context(READING_TAGS)?;
+        tags.sort_by_cached_key(|tag| tag.name.to_lowercase());
+        Ok(tags)
+    }
+
+    /// Parse the deletion log from the `graves` table
+    /// Returns an empty list when the collection has no such table
+    pub fn parse_graves(&self) -> Result<Vec<GraveEntry>, AnkiError> {
+        if !self.table_exists("graves") {
+            return Ok(Vec::new());
+        }
+
+        // Columns by name: legacy and modern schemas order them differently
+        let mut stmt = self.conn
+            .prepare("SELECT oid, type FROM graves")
+            .context(READING_GRAVES)?;
+        let rows = stmt.query_map([], |row| {
+            Ok(GraveEntry {
+                oid: value_to_i64(row.get_ref(0)?),
+                type_: row.get(1)?,
+            })
+        }).context(READING_GRAVES)?;
+
+        rows.collect::<Result<Vec<_>, _>>().context(READING_GRAVES)
+    }
+
+    /// Collection schema version (`col.ver`): 11 for legacy collections, up to
+    /// `MAX_SCHEMA_VERSION` for current ones
+    pub fn schema_version(&self) -> Result<u32, AnkiError> {
+        self.conn
+            .query_row("SELECT ver FROM col", [], |row| row.get(0))
+            .context(READING_COLLECTION_META)
+    }
+
+    /// Parse collection creation time and scheduler settings
+    /// Settings live in the `config` table on modern schemas and in the
+    /// `col.conf` JSON on legacy ones; missing keys use Anki's defaults
+    pub fn parse_collection_meta(&self) -> Result<CollectionMeta, AnkiError> {
+        let (created_at, conf_json): (i64, Option<String>) = self.conn.query_row(
+            "SELECT crt, conf FROM col",
+            [],
+            |row| Ok((row.get(0)?, row.get::<_, Option<String>>(1).ok().flatten())),
+        ).context(READING_COLLECTION_META)?;
+
+        let mut conf: serde_json::Map<String, Value> = conf_json
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+
+        // Modern schema: one JSON value per key in the config table
+        if self.table_exists("config") {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT KEY, val FROM config")
+                .context(READING_COLLECTION_META)?;
+            let rows = stmt.query_map([], |row| {
+                Ok((value_to_string(row.get_ref(0)?), value_to_string(row.get_ref(1)?)))
+            }).context(READING_COLLECTION_META)?;
+            for row_result in rows {
+                let (key, val) = row_result.context(READING_COLLECTION_META)?;
+                if let Ok(value) = serde_json::from_str(&val) {
+                    conf.insert(key, value);
+                }
+            }
+        }
+
+        let defaults = CollectionMeta::default();
+        Ok(CollectionMeta {
+            created_at,
+            scheduler_version: conf
+                .get("schedVer")
+                .and_then(Value::as_u64)
+                .map_or(defaults.scheduler_version, |v| v as u32),
+            rollover_hour: conf
+                .get("rollover")
+                .and_then(Value::as_u64)
+                .filter(|&h| h < 24)
+                .map_or(defaults.rollover_hour, |h| h as u8),
+            creation_offset: conf
+                .get("creationOffset")
+                .and_then(Value::as_i64)
+                .map(|v| v as i32),
+        })
+    }
+
+    /// Check whether a table exists in the database
+    fn table_exists(&self, name: &str) -> bool {
+        self.conn.query_row(
+            "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type='table' AND name=?1",
+            [name],
+            |row| row.get(0),
+        ).unwrap_or(false)
+    }
+
     /// Get the total number of cards in the database
     pub fn card_count(&self) -> Result<usize, AnkiError> {
         let count: i64 = self.conn.query_row(
             "SELECT COUNT(*) FROM cards",
             [],
             |row| row.get(0),
-        )?;
+        ).context("counting cards")?;
         Ok(count as usize)
     }
 
-    /// Parse all cards with their notes
-    /// Returns cards grouped by deck ID
+    /// Get the total number of notes in the database
+    /// A note has a card per template (or cloze number), so this is usually below `card_count`
+    pub fn note_count(&self) -> Result<usize, AnkiError> {
+        let count: i64 = self.conn.query_row(
+            "SELECT COUNT(*) FROM notes",
+            [],
+            |row| row.get(0),
+        ).context("counting notes")?;
+        Ok(count as usize)
+    }
+
+    /// Card IDs grouped by home deck ID (see `AnkiCard::deck_id`), without reading notes
+    /// Much cheaper than `parse_cards` when only deck membership is needed
+    pub fn card_ids_by_deck(&self) -> Result<HashMap<i64, Vec<i64>>, AnkiError> {
+        let mut stmt = self.conn
+            .prepare("SELECT id, did, odid FROM cards ORDER BY id")
+            .context(READING_CARDS)?;
+        let rows = stmt.query_map([], |row| {
+            let original_deck_id = value_to_i64(row.get_ref(2)?);
+            let deck_id = if original_deck_id != 0 {
+                original_deck_id
+            } else {
+                value_to_i64(row.get_ref(1)?)
+            };
+            Ok((value_to_i64(row.get_ref(0)?), deck_id))
+        }).context(READING_CARDS)?;
+
+        let mut ids_by_deck: HashMap<i64, Vec<i64>> = HashMap::new();
+        for row_result in rows {
+            let (id, deck_id) = row_result.context(READING_CARDS)?;
+            ids_by_deck.entry(deck_id).or_default().push(id);
+        }
+        Ok(ids_by_deck)
+    }
+
+    /// Parse all cards with their notes, keeping raw field HTML
+    /// Returns cards grouped by deck ID, each deck ordered by ordinal then card ID
     pub fn parse_cards<F>(
         &self,
+        progress_callback: F,
+    ) -> Result<HashMap<i64, Vec<AnkiCard>>, AnkiError>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.parse_cards_with_options(&ParseOptions::default(), progress_callback)
+    }
+
+    /// Parse all cards with their notes
+    /// Returns cards grouped by deck ID, each deck ordered by ordinal then card ID
+    pub fn parse_cards_with_options<F>(
+        &self,
+        options: &ParseOptions,
+        progress_callback: F,
+    ) -> Result<HashMap<i64, Vec<AnkiCard>>, AnkiError>
+    where
+        F: FnMut(usize, usize),
+    {
+        self.parse_cards_until(options, options.deadline(), progress_callback)
+    }
+
+    /// Parse all cards, failing with `AnkiError::Timeout` once `deadline` passes
+    pub fn parse_cards_until<F>(
+        &self,
+        options: &ParseOptions,
+        deadline: Option<Instant>,
         mut progress_callback: F,
     ) -> Result<HashMap<i64, Vec<AnkiCard>>, AnkiError>
     where
@@ -204,74 +689,74 @@ impl AnkiDatabase {
     {
         let total = self.card_count()?;
         let mut cards_by_deck: HashMap<i64, Vec<AnkiCard>> = HashMap::new();
+        let reader = CardRowReader::new(self, options)?;
 
-        // Regex patterns for extracting media references
-        let sound_regex = Regex::new(r"\[sound:([^\]]+)\]").unwrap();
-        let img_regex = Regex::new(r#"<img[^>]+src=["']?([^"'\s>]+)["']?"#).unwrap();
-
-        // Query cards joined with notes
-        let mut stmt = self.conn.prepare(
-            "SELECT c.id, c.nid, c.did, n.flds
-             FROM cards c
-             JOIN notes n ON c.nid = n.id"
-        )?;
+        // Ordered so repeated parses give identical output
+        let sql = format!("{CARD_SELECT} ORDER BY c.ord, c.id");
+        let mut stmt = self.conn.prepare(&sql).context(READING_CARDS)?;
 
         let mut current = 0;
-        let rows = stmt.query_map([], |row| {
-            let id: i64 = row.get(0)?;
-            let note_id: i64 = row.get(1)?;
-            let deck_id: i64 = row.get(2)?;
-            // Get fields - handle both Text and Blob column types
-            let fields_str: String = match row.get_ref(3)? {
-                rusqlite::types::ValueRef::Text(bytes) => {
-                    String::from_utf8_lossy(bytes).into_owned()
-                }
-                rusqlite::types::ValueRef::Blob(bytes) => {
-                    String::from_utf8_lossy(bytes).into_owned()
-                }
-                _ => String::new(),
-            };
-            Ok((id, note_id, deck_id, fields_str))
-        })?;
+        let mut missing_notes = 0u32;
+        let rows = stmt.query_map([], |row| reader.read(row)).context(READING_CARDS)?;
 
         for row_result in rows {
-            let (id, note_id, deck_id, fields_str) = row_result?;
-
-            // Fields are separated by 0x1f (unit separator)
-            let fields: Vec<String> = fields_str
-                .split('\x1f')
-                .map(|s| s.to_string())
-                .collect();
-
-            // Extract media references from all fields
-            let media_references = extract_media_references(&fields, &sound_regex, &img_regex);
-
-            let card = AnkiCard {
-                id,
-                note_id,
-                deck_id,
-                fields,
-                media_references,
-            };
+            if let Some(card) = row_result.context(READING_CARDS)? {
+                if card.note_missing {
+                    missing_notes += 1;
+                }
 
-            cards_by_deck
-                .entry(deck_id)
-                .or_default()
-                .push(card);
+                cards_by_deck
+                    .entry(card.deck_id)
+                    .or_default()
+                    .push(card);
+            }
 
             current += 1;
 
-            // Report progress every BATCH_SIZE cards
+            // Report progress and check the deadline every BATCH_SIZE cards
             if current % BATCH_SIZE == 0 {
                 progress_callback(current, total);
+                check_deadline(deadline)?;
             }
         }
 
         // Final progress update
         progress_callback(current, total);
 
+        if missing_notes > 0 {
+            log::warn!("{} cards reference notes that don't exist", missing_notes);
+            options.emit(ParseEvent::NotesMissing { count: missing_notes });
+        }
+
         Ok(cards_by_deck)
     }
+
+    /// Parse one page of a deck's cards, ordered by card ID
+    /// Fields keep their raw HTML, as with `parse_cards`
+    pub fn parse_cards_page(
+        &self,
+        deck_id: i64,
+        offset: u32,
+        limit: u32,
+    ) -> Result<Vec<AnkiCard>, AnkiError> {
+        let options = ParseOptions::default();
+        let reader = CardRowReader::new(self, &options)?;
+
+        let sql = format!(
+            "{CARD_SELECT} WHERE (CASE WHEN c.odid != 0 THEN c.odid ELSE c.did END) = ?1
+             ORDER BY c.id LIMIT ?2 OFFSET ?3"
+        );
+        let mut stmt = self.conn.prepare(&sql).context(READING_CARDS)?;
+        let rows = stmt
+            .query_map((deck_id, limit, offset), |row| reader.read(row))
+            .context(READING_CARDS)?;
+
+        let mut cards = Vec::new();
+        for row_result in rows {
+            cards.extend(row_result.context(READING_CARDS)?);
+        }
+        Ok(cards)
+    }
 }
 
 impl Drop for AnkiDatabase {
@@ -283,6 +768,405 @@ impl Drop for AnkiDatabase {
     }
 }
 
+/// Cards joined with their notes, in the column order `CardRowReader::read` expects.
+/// LEFT JOIN so cards whose note row is missing (corrupt/partial exports) are
+/// kept rather than dropped
+const CARD_SELECT: &str =
+    "SELECT c.id, c.nid, c.did, n.flds, n.guid, n.sfld, n.mid, c.ord, n.id IS NULL, c.flags, c.queue,
+            c.type, c.ivl, n.tags, c.mod, n.mod, c.odid, c.due, c.odue
+     FROM cards c
+     LEFT JOIN notes n ON c.nid = n.id";
+
+/// Builds `AnkiCard`s from `CARD_SELECT` rows
+struct CardRowReader<'a> {
+    options: &'a ParseOptions,
+    /// Note types for rendering question/answer; cards still parse without them
+    note_types: &'a HashMap<i64, AnkiNoteType>,
+    /// Media named by each note type's CSS, e.g. fonts and background images
+    css_references: HashMap<i64, Vec<String>>,
+    /// Cleaning options, including the compiled `ParseOptions.sound_patterns`
+    html_options: HtmlOptions,
+}
+
+impl<'a> CardRowReader<'a> {
+    fn new(db: &'a AnkiDatabase, options: &'a ParseOptions) -> Result<Self, AnkiError> {
+        let html_options = HtmlOptions {
+            sound_patterns: compile_sound_patterns(&options.sound_patterns)?,
+            collapse_spaces: options.collapse_spaces,
+        };
+        let note_types = db.note_types_by_id();
+        let css_references = note_types
+            .values()
+            .map(|nt| (nt.id, extract_css_media_references(&nt.css)))
+            .filter(|(_, refs)| !refs.is_empty())
+            .collect();
+        Ok(Self {
+            options,
+            note_types,
+            css_references,
+            html_options,
+        })
+    }
+
+    /// Read one row, or None if the card is excluded by the tag filter
+    fn read(&self, row: &rusqlite::Row<'_>) -> rusqlite::Result<Option<AnkiCard>> {
+        let options = self.options;
+        let note_missing: bool = row.get(8)?;
+        let queue: i32 = row.get(10)?;
+        let card_type: u8 = row.get(11)?;
+
+        let tags: Vec<String> = value_to_string(row.get_ref(13)?)
+            .split_whitespace()
+            .map(|t| t.to_string())
+            .collect();
+        // Skip cards outside the tag filter before doing any field work
+        if let Some(filter) = &options.tag_filter {
+            if !tags_match(&tags, filter) {
+                return Ok(None);
+            }
+        }
+
+        // Get fields - handle both Text and Blob column types
+        let fields_str = if options.repair_encoding {
+            value_to_string_repaired(row.get_ref(3)?)
+        } else {
+            value_to_string(row.get_ref(3)?)
+        };
+
+        let fields: Vec<String> = if note_missing {
+            Vec::new()
+        } else {
+            split_fields(&fields_str)
+        };
+
+        let note_type_id: i64 = row.get::<_, Option<i64>>(6)?.unwrap_or(0);
+
+        // Extract media references from all fields, then the note type's styling
+        let mut media_references =
+            extract_media_references(&fields, &self.html_options.sound_patterns);
+        for reference in self.css_references.get(&note_type_id).into_iter().flatten() {
+            if !media_references.contains(reference) {
+                media_references.push(reference.clone());
+            }
+        }
+        let ordinal: u32 = row.get(7)?;
+        let (mut question, mut answer) = self
+            .note_types
+            .get(&note_type_id)
+            .and_then(|nt| template::render_card(nt, ordinal, &fields))
+            .unzip();
+
+        // Keep the original HTML alongside cleaned fields
+        let (mut fields, mut raw_fields) = if options.clean_html {
+            let html_options = &self.html_options;
+            question = question.map(|q| clean_html_with(&q, html_options));
+            answer = answer.map(|a| clean_html_with(&a, html_options));
+            (process_card_fields_with(&fields, html_options), fields)
+        } else {
+            (fields, Vec::new())
+        };
+
+        // Trimmed after rendering so templates still see every field
+        if options.trim_empty_trailing_fields {
+            let len = fields.iter().rposition(|f| !f.is_empty()).map_or(0, |i| i + 1);
+            fields.truncate(len);
+            raw_fields.truncate(len);
+        }
+
+        // Cards in a filtered deck belong to their original deck (odid)
+        let current_deck_id = value_to_i64(row.get_ref(2)?);
+        let original_deck_id = value_to_i64(row.get_ref(16)?);
+        let (deck_id, filtered_deck_id) = if original_deck_id != 0 {
+            (original_deck_id, Some(current_deck_id))
+        } else {
+            (current_deck_id, None)
+        };
+        // ...and so does their due, kept in odue while filtered
+        let original_due: i64 = row.get(18)?;
+        let due = if original_deck_id != 0 && original_due != 0 {
+            original_due
+        } else {
+            row.get(17)?
+        };
+
+        Ok(Some(AnkiCard {
+            // Some exporters store these ids as little-endian blobs
+            id: value_to_i64(row.get_ref(0)?),
+            note_id: value_to_i64(row.get_ref(1)?),
+            note_guid: value_to_string(row.get_ref(4)?),
+            deck_id,
+            filtered_deck_id,
+            note_type_id,
+            ordinal,
+            fields,
+            raw_fields,
+            // sfld is stored as an integer when the sort field is numeric
+            sort_field: value_to_string(row.get_ref(5)?),
+            tags,
+            media_references,
+            question,
+            answer,
+            note_missing,
+            // User flag lives in the low 3 bits
+            flag: (row.get::<_, i64>(9)? & 0b111) as u8,
+            queue,
+            due,
+            is_suspended: queue == QUEUE_SUSPENDED,
+            is_buried: queue == QUEUE_SCHED_BURIED || queue == QUEUE_USER_BURIED,
+            card_type,
+            is_new: card_type == CARD_TYPE_NEW,
+            interval: row.get(12)?,
+            card_modified: row.get(14)?,
+            note_modified: row.get::<_, Option<i64>>(15)?.unwrap_or(0),
+        }))
+    }
+}
+
+/// Separator between note fields in `notes.flds` (ASCII unit separator, 0x1f)
+pub const FIELD_SEPARATOR: char = '\x1f';
+
+/// Split a raw `notes.flds` value into its fields on `FIELD_SEPARATOR`
+/// An empty string is a single empty field, as in Anki
+pub fn split_fields(flds: &str) -> Vec<String> {
+    flds.split(FIELD_SEPARATOR).map(|s| s.to_string()).collect()
+}
+
+/// Join fields into a `notes.flds` value; the inverse of `split_fields`
+pub fn join_fields(fields: &[String]) -> String {
+    let mut flds = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            flds.push(FIELD_SEPARATOR);
+        }
+        flds.push_str(field);
+    }
+    flds
+}
+
+/// Check that data starts with the SQLite magic header
+fn check_sqlite_header(data: &[u8]) -> Result<(), AnkiError> {
+    if data.starts_with(SQLITE_HEADER) {
+        Ok(())
+    } else {
+        Err(AnkiError::InvalidDatabaseHeader)
+    }
+}
+
+/// Build a temp file path in `dir` that is unique within this process
+pub(crate) fn unique_temp_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+        "anki_import_{}_{}.db",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// Sort decks by hierarchy depth (parents before children), then by the given mode
+fn sort_decks(decks: &mut [AnkiDeck], sort_mode: DeckSortMode) {
+    decks.sort_by(|a, b| {
+        a.depth.cmp(&b.depth).then_with(|| match sort_mode {
+            DeckSortMode::Alphabetical => a.name.cmp(&b.name),
+            DeckSortMode::Original => a.order.cmp(&b.order),
+        })
+    });
+}
+
+/// Rewrite a deck name's hierarchy separator to Anki's "::"
+fn canonical_deck_name(name: String, separator: Option<&str>) -> String {
+    match separator {
+        Some(sep) if !sep.is_empty() && sep != "::" => name.replace(sep, "::"),
+        _ => name,
+    }
+}
+
+/// Read an integer column that may be stored as a little-endian blob
+/// Anything else (including blobs shorter than 8 bytes) reads as 0
+fn value_to_i64(value: ValueRef<'_>) -> i64 {
+    match value {
+        ValueRef::Integer(i) => i,
+        ValueRef::Blob(bytes) if bytes.len() >= 8 => {
+            i64::from_le_bytes(bytes[..8].try_into().unwrap_or([0; 8]))
+        }
+        _ => 0,
+    }
+}
+
+/// Read a column as a string, whatever its storage class
+fn value_to_string(value: ValueRef<'_>) -> String {
+    match value {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Null => String::new(),
+    }
+}
+
+/// Check whether any tag matches any filter entry, ignoring case
+/// A filter also matches its subtags: "lang" matches "lang::jp"
+fn tags_match(tags: &[String], filter: &[String]) -> bool {
+    tags.iter().any(|tag| {
+        let tag = tag.to_lowercase();
+        filter.iter().any(|f| {
+            let f = f.to_lowercase();
+            tag == f || tag.strip_prefix(&f).is_some_and(|rest| rest.starts_with("::"))
+        })
+    })
+}
+
+/// Read note fields as a string, repairing legacy encodings
+///
+/// Bytes that aren't valid UTF-8 are decoded as Latin-1 if that reads better
+/// than the lossy UTF-8 decoding; valid text has each field run through
+/// `repair_mojibake`.
+fn value_to_string_repaired(value: ValueRef<'_>) -> String {
+    let bytes = match value {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes,
+        _ => return value_to_string(value),
+    };
+
+    match std::str::from_utf8(bytes) {
+        Ok(text) => {
+            join_fields(&text.split(FIELD_SEPARATOR).map(repair_mojibake).collect::<Vec<_>>())
+        }
+        Err(_) => {
+            let lossy = String::from_utf8_lossy(bytes).into_owned();
+            let latin1: String = bytes.iter().map(|&b| char::from(b)).collect();
+            if suspicion_score(&latin1) < suspicion_score(&lossy) {
+                latin1
+            } else {
+                lossy
+            }
+        }
+    }
+}
+
+/// Undo double encoding: UTF-8 text that was decoded as Latin-1/Windows-1252
+/// and re-encoded, e.g. "cafÃ©" → "café" or "Itâ€™s" → "It’s"
+///
+/// The field is only changed if reversing the bad decoding yields valid UTF-8
+/// that looks more like real text, so correct fields are left alone.
+fn repair_mojibake(field: &str) -> String {
+    if suspicion_score(field) == 0 {
+        return field.to_string();
+    }
+
+    let bytes: Option<Vec<u8>> = field.chars().map(windows_1252_byte).collect();
+    match bytes.map(String::from_utf8) {
+        Some(Ok(repaired)) if suspicion_score(&repaired) < suspicion_score(field) => repaired,
+        _ => field.to_string(),
+    }
+}
+
+/// Count signs of badly decoded text: replacement characters, control
+/// characters, and UTF-8 lead bytes decoded as Latin-1 followed by a
+/// continuation byte (the "Ã©" pattern)
+fn suspicion_score(text: &str) -> usize {
+    let mut score = 0;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\u{FFFD}' || (c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x1f')) {
+            score += 1;
+        }
+        let is_lead = matches!(windows_1252_byte(c), Some(0xC2..=0xF4));
+        let next_is_continuation =
+            matches!(chars.peek().copied().and_then(windows_1252_byte), Some(0x80..=0xBF));
+        if is_lead && next_is_continuation {
+            score += 1;
+        }
+    }
+    score
+}
+
+/// The Windows-1252 byte a character decodes from, if any
+/// Latin-1 covers U+0000–U+00FF; Windows-1252 maps 0x80–0x9F to punctuation
+fn windows_1252_byte(c: char) -> Option<u8> {
+    const HIGH: [(char, u8); 27] = [
+        ('€', 0x80), ('‚', 0x82), ('ƒ', 0x83), ('„', 0x84), ('…', 0x85), ('†', 0x86),
+        ('‡', 0x87), ('ˆ', 0x88), ('‰', 0x89), ('Š', 0x8A), ('‹', 0x8B), ('Œ', 0x8C),
+        ('Ž', 0x8E), ('\u{2018}', 0x91), ('\u{2019}', 0x92), ('\u{201C}', 0x93),
+        ('\u{201D}', 0x94), ('•', 0x95), ('–', 0x96), ('—', 0x97), ('˜', 0x98), ('™', 0x99),
+        ('š', 0x9A), ('›', 0x9B), ('œ', 0x9C), ('ž', 0x9E), ('Ÿ', 0x9F),
+    ];
+    match u32::from(c) {
+        code @ 0..=0xFF => Some(code as u8),
+        _ => HIGH.iter().find(|(ch, _)| *ch == c).map(|(_, byte)| *byte),
+    }
+}
+
+/// Read a protobuf varint at `*pos`, advancing past it
+pub(crate) fn read_varint(data: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    while *pos < data.len() && shift < 64 {
+        let byte = data[*pos];
+        *pos += 1;
+        value |= u64::from(byte & 0x7F) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+    None
+}
+
+/// Find the first length-delimited field with the given number in a protobuf message
+fn protobuf_bytes_field(data: &[u8], field: u64) -> Option<&[u8]> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let (number, wire_type) = (key >> 3, key & 0x07);
+        match wire_type {
+            0 => {
+                read_varint(data, &mut pos)?;
+            }
+            1 => pos += 8,
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|&end| end <= data.len())?;
+                if number == field {
+                    return Some(&data[pos..end]);
+                }
+                pos = end;
+            }
+            5 => pos += 4,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Find the first varint field with the given number in a protobuf message
+fn protobuf_varint_field(data: &[u8], field: u64) -> Option<u64> {
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let (number, wire_type) = (key >> 3, key & 0x07);
+        match wire_type {
+            0 => {
+                let value = read_varint(data, &mut pos)?;
+                if number == field {
+                    return Some(value);
+                }
+            }
+            1 => pos += 8,
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                pos = pos.checked_add(len).filter(|&end| end <= data.len())?;
+            }
+            5 => pos += 4,
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Find the first string field with the given number in a protobuf message
+fn protobuf_string_field(data: &[u8], field: u64) -> Option<String> {
+    protobuf_bytes_field(data, field).map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+}
+
 /// Extract deck name from protobuf-encoded data
 /// Anki 2.1.50+ stores deck data as protobuf in the 'decks' table
 /// The name field is typically field 2 (wire type 2 = length-delimited)
@@ -347,68 +1231,783 @@ fn extract_name_from_protobuf(data: &[u8]) -> Option<String> {
     None
 }
 
-/// Extract media references from card fields
-fn extract_media_references(
-    fields: &[String],
-    sound_regex: &Regex,
-    img_regex: &Regex,
-) -> Vec<String> {
+/// `<style>` blocks (group 1) and `style="..."` attributes (group 2 or 3)
+static STYLE_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<style[^>]*>(.*?)</style>|\bstyle\s*=\s*(?:"([^"]*)"|'([^']*)')"#)
+        .unwrap()
+});
+
+/// CSS `url(file.png)`, quoted or not
+static CSS_URL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"(?i)url\(\s*["']?([^"')]+?)["']?\s*\)"#).unwrap());
+
+/// Extract media references from card fields, without duplicates
+///
+/// `extra_sound` holds the compiled `ParseOptions.sound_patterns`.
+fn extract_media_references(fields: &[String], extra_sound: &[Regex]) -> Vec<String> {
     let mut refs = Vec::new();
 
     for field in fields {
-        // Extract [sound:filename.mp3] references
-        for cap in sound_regex.captures_iter(field) {
+        extract_media_references_from(field, extra_sound, &mut refs);
+    }
+
+    // Corrupted notes can have a tag split across a field separator;
+    // look at the fields joined back together before giving up
+    if refs.is_empty() && fields.len() > 1 {
+        extract_media_references_from(&fields.concat(), extra_sound, &mut refs);
+    }
+
+    let mut seen = HashSet::new();
+    refs.retain(|r| seen.insert(r.clone()));
+    refs
+}
+
+/// Push the media references found in `text` onto `refs`
+fn extract_media_references_from(text: &str, extra_sound: &[Regex], refs: &mut Vec<String>) {
+    // Extract [sound:filename.mp3] references
+    for regex in std::iter::once(&*SOUND_REGEX).chain(extra_sound) {
+        for cap in regex.captures_iter(text) {
             if let Some(filename) = cap.get(1) {
-                refs.push(filename.as_str().to_string());
+                refs.push(decode_html_entities(&unescape_sound_filename(filename.as_str())));
             }
         }
+    }
 
-        // Extract <img src="filename.jpg"> references
-        for cap in img_regex.captures_iter(field) {
-            if let Some(filename) = cap.get(1) {
-                refs.push(filename.as_str().to_string());
-            }
+    // Extract <img src="filename.jpg"> references
+    for cap in IMG_SRC_REGEX.captures_iter(text) {
+        if let Some(filename) = cap.get(1) {
+            refs.push(decode_html_entities(filename.as_str()));
         }
     }
 
+    // Extract url(filename.png) from <style> blocks and style attributes,
+    // skipping inline data and remote images
+    for cap in STYLE_REGEX.captures_iter(text) {
+        let Some(css) = cap.get(1).or_else(|| cap.get(2)).or_else(|| cap.get(3)) else {
+            continue;
+        };
+        refs.extend(extract_css_media_references(&decode_html_entities(css.as_str())));
+    }
+}
+
+/// Media files named by `url(...)` in a stylesheet, without duplicates
+/// Inline data and remote URLs are skipped
+fn extract_css_media_references(css: &str) -> Vec<String> {
+    let mut refs: Vec<String> = Vec::new();
+    for url in CSS_URL_REGEX.captures_iter(css) {
+        let filename = url[1].trim();
+        let lower = filename.to_ascii_lowercase();
+        let remote = ["data:", "http:", "https:", "//"].iter().any(|p| lower.starts_with(p));
+        if !remote && !refs.iter().any(|r| r == filename) {
+            refs.push(filename.to_string());
+        }
+    }
     refs
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
 
+    /// Minimal legacy (schema 11) Anki tables
+    pub(crate) const SCHEMA: &str = "
+        CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null,
+            scm integer not null, ver integer not null, dty integer not null, usn integer not null,
+            ls integer not null, conf text not null, models text not null, decks text not null,
+            dconf text not null, tags text not null);
+        CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null,
+            mod integer not null, usn integer not null, tags text not null, flds text not null,
+            sfld integer not null, csum integer not null, flags integer not null, data text not null);
+        CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null,
+            ord integer not null, mod integer not null, usn integer not null, type integer not null,
+            queue integer not null, due integer not null, ivl integer not null, factor integer not null,
+            reps integer not null, lapses integer not null, left integer not null, odue integer not null,
+            odid integer not null, flags integer not null, data text not null);
+    ";
+
+    /// Build a database from SQL run against an empty scratch file
+    pub(crate) fn database_from_sql(sql: &str) -> AnkiDatabase {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(file.path()).unwrap();
+            conn.execute_batch(sql).unwrap();
+        }
+        let data = std::fs::read(file.path()).unwrap();
+        AnkiDatabase::open_from_bytes(&data).unwrap()
+    }
+
+    /// Legacy `col` row with a Basic note type (id 1)
+    pub(crate) const COL_ROW: &str = r#"
+        INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{}',
+            '{"1": {"name": "Basic", "flds": [{"name": "Front", "ord": 0}, {"name": "Back", "ord": 1}],
+                    "tmpls": [{"name": "Card 1", "ord": 0, "qfmt": "{{Front}}",
+                               "afmt": "{{FrontSide}}<hr id=answer>{{Back}}"}]}}',
+            '{"1": {"name": "Default"}}', '{}', '{}');
+    "#;
+
+    /// Insert a note and a single card for it
+    pub(crate) fn insert_note_sql(card_id: i64, note_id: i64, deck_id: i64, flds: &str) -> String {
+        format!(
+            "INSERT INTO notes VALUES ({note_id}, 'guid{note_id}', 1, 0, 0, '', '{flds}', '', 0, 0, '');
+             INSERT INTO cards VALUES ({card_id}, {note_id}, {deck_id}, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');"
+        )
+    }
+
     #[test]
-    fn test_extract_media_references() {
-        let sound_regex = Regex::new(r"\[sound:([^\]]+)\]").unwrap();
-        let img_regex = Regex::new(r#"<img[^>]+src=["']?([^"'\s>]+)["']?"#).unwrap();
+    fn test_split_and_join_fields() {
+        let fields = split_fields("front\x1f<b>back</b>\x1f");
+        assert_eq!(fields, vec!["front", "<b>back</b>", ""]);
+        assert_eq!(join_fields(&fields), "front\x1f<b>back</b>\x1f");
+        assert_eq!(split_fields(""), vec![""]);
+        assert_eq!(join_fields(&[]), "");
+    }
 
+    #[test]
+    fn test_extract_media_references() {
         let fields = vec![
             "[sound:audio.mp3] Some text".to_string(),
             "<img src=\"image.jpg\">".to_string(),
             "Plain text".to_string(),
             "[sound:korean_audio.wav]<img src='photo.png'>".to_string(),
+            "[sound:tom&amp;jerry.mp3]".to_string(),
         ];
 
-        let refs = extract_media_references(&fields, &sound_regex, &img_regex);
+        let refs = extract_media_references(&fields, &[]);
 
-        assert_eq!(refs.len(), 4);
+        assert_eq!(refs.len(), 5);
+        assert!(refs.contains(&"tom&jerry.mp3".to_string()));
         assert!(refs.contains(&"audio.mp3".to_string()));
         assert!(refs.contains(&"image.jpg".to_string()));
         assert!(refs.contains(&"korean_audio.wav".to_string()));
         assert!(refs.contains(&"photo.png".to_string()));
     }
 
+    #[test]
+    fn test_extract_media_references_edge_cases() {
+        // Escaped bracket inside a sound filename
+        let fields = vec![r"[sound:take \]2\[.mp3]".to_string()];
+        let refs = extract_media_references(&fields, &[]);
+        assert_eq!(refs, vec!["take ]2[.mp3"]);
+
+        // <img> split across a field separator
+        let fields = vec!["<img ".to_string(), "src=\"split.png\">".to_string()];
+        let refs = extract_media_references(&fields, &[]);
+        assert_eq!(refs, vec!["split.png"]);
+    }
+
+    #[test]
+    fn test_extract_css_media_references() {
+        let fields = vec![
+            "<style>.card { background: url('bg.png') } .x { background: url(data:image/png;base64,AA) }\
+             </style>front"
+                .to_string(),
+            "<img src=\"bg.png\">".to_string(),
+        ];
+        assert_eq!(extract_media_references(&fields, &[]), vec!["bg.png"]);
+
+        let fields = vec![
+            r#"<div style="background-image:url(&quot;tile.jpg&quot;)">x</div>"#.to_string(),
+            "<span style='background: URL( stripe.gif )'></span>".to_string(),
+            r#"<div style="background:url(https://example.com/remote.png)"></div>"#.to_string(),
+            // Not inside a style, so not a reference
+            "see url(plain.png)".to_string(),
+        ];
+        assert_eq!(extract_media_references(&fields, &[]), vec!["tile.jpg", "stripe.gif"]);
+    }
+
+    #[test]
+    fn test_note_type_css_media_references() {
+        // The field's own reference to the font isn't repeated
+        let field = r#"<img src="a.png"><style>p { src: url(_kanji.ttf) }</style>"#;
+        let db = database_from_sql(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}',
+                '{{"1": {{"name": "Basic", "flds": [{{"name": "Front", "ord": 0}}],
+                        "css": "@font-face {{ src: url(\"_kanji.ttf\") }}"}}}}',
+                '{{"1": {{"name": "Default"}}}}', '{{}}', '{{}}');
+            {}"#,
+            insert_note_sql(10, 20, 1, field)
+        ));
+
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        assert_eq!(cards[&1][0].media_references, vec!["a.png", "_kanji.ttf"]);
+    }
+
     #[test]
     fn test_deck_hierarchy() {
         let deck = AnkiDeck::from_name(1, "Parent::Child::Grandchild".to_string());
         assert_eq!(deck.short_name, "Grandchild");
         assert_eq!(deck.parent_path(), Some("Parent::Child".to_string()));
         assert!(!deck.is_root());
+        assert_eq!(deck.depth(), 2);
 
         let root_deck = AnkiDeck::from_name(2, "RootDeck".to_string());
         assert_eq!(root_deck.short_name, "RootDeck");
         assert_eq!(root_deck.parent_path(), None);
         assert!(root_deck.is_root());
+        assert_eq!(root_deck.depth(), 0);
+    }
+
+    #[test]
+    fn test_database_error_names_operation() {
+        let db = database_from_sql("CREATE TABLE cards (id INTEGER PRIMARY KEY);");
+        match db.parse_cards(|_, _| {}) {
+            Err(AnkiError::DatabaseError(message)) => {
+                assert!(message.starts_with("while reading cards: "), "{message}");
+            }
+            other => panic!("unexpected {:?}", other.map(|cards| cards.len())),
+        }
+    }
+
+    #[test]
+    fn test_parse_cards_note_guid() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{}",
+            insert_note_sql(10, 20, 1, "front\x1fback")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        assert_eq!(cards[&1][0].note_guid, "guid20");
+    }
+
+    #[test]
+    fn test_parse_cards_sort_field() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{}
+             UPDATE notes SET sfld = 'back' WHERE id = 20;
+             INSERT INTO notes VALUES (21, 'g', 1, 0, 0, '', '42', 42, 0, 0, '');
+             INSERT INTO cards VALUES (11, 21, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "front\x1fback")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let by_id = |id: i64| cards[&1].iter().find(|c| c.id == id).unwrap();
+        assert_eq!(by_id(10).sort_field, "back");
+        // Numeric sort fields come back as integers and are coerced
+        assert_eq!(by_id(11).sort_field, "42");
+    }
+
+    #[test]
+    fn test_parse_note_types_legacy() {
+        let db = database_from_sql(&format!("{SCHEMA}{COL_ROW}"));
+        let note_types = db.parse_note_types().unwrap();
+        assert_eq!(note_types.len(), 1);
+        assert_eq!(note_types[0].name, "Basic");
+        assert!(!note_types[0].is_cloze);
+        assert_eq!(note_types[0].field_names, vec!["Front", "Back"]);
+        assert_eq!(note_types[0].templates[0].question_format, "{{Front}}");
+    }
+
+    #[test]
+    fn test_parse_note_types_modern() {
+        // Template config protobuf: field 1 = "{{Front}}", field 2 = "{{Back}}"
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}
+             CREATE TABLE notetypes (id integer primary key, name text, mtime_secs integer,
+                 usn integer, config blob);
+             CREATE TABLE fields (ntid integer, ord integer, name text, config blob);
+             CREATE TABLE templates (ntid integer, ord integer, name text, mtime_secs integer,
+                 usn integer, config blob);
+             INSERT INTO notetypes VALUES (5, 'Modern', 0, 0, x'1a082e63617264207b7d'),
+                 (6, 'Cloze', 0, 0, x'0801');
+             INSERT INTO fields VALUES (5, 1, 'Back', x''), (5, 0, 'Front', x'');
+             INSERT INTO templates VALUES (5, 0, 'Card 1', 0, 0,
+                 x'0a097b7b46726f6e747d7d12087b7b4261636b7d7d');"
+        ));
+        let note_types = db.parse_note_types().unwrap();
+        assert_eq!(note_types.len(), 2);
+        assert!(!note_types[0].is_cloze);
+        // Config field 1 (kind) = 1
+        assert!(note_types[1].is_cloze);
+        assert_eq!(note_types[0].field_names, vec!["Front", "Back"]);
+        assert_eq!(note_types[0].templates[0].question_format, "{{Front}}");
+        assert_eq!(note_types[0].templates[0].answer_format, "{{Back}}");
+        // Config field 3 (css) = ".card {}"
+        assert_eq!(note_types[0].css, ".card {}");
+    }
+
+    #[test]
+    fn test_parse_cards_renders_templates() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}",
+            insert_note_sql(10, 20, 1, "front\x1fback")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let card = &cards[&1][0];
+        assert_eq!(card.question.as_deref(), Some("front"));
+        assert_eq!(card.answer.as_deref(), Some("front<hr id=answer>back"));
+    }
+
+    #[test]
+    fn test_deck_sort_modes() {
+        let db = database_from_sql(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{{}}',
+                '{{"30": {{"name": "B"}}, "10": {{"name": "C"}}, "20": {{"name": "A"}},
+                   "5": {{"name": "C::Child"}}}}', '{{}}', '{{}}');"#
+        ));
+
+        let names = |decks: Vec<AnkiDeck>| decks.into_iter().map(|d| d.name).collect::<Vec<_>>();
+
+        let original = db.parse_decks(DeckSortMode::Original).unwrap();
+        assert_eq!(original.iter().find(|d| d.id == 5).unwrap().order, 0);
+        assert_eq!(names(original), vec!["C", "A", "B", "C::Child"]);
+
+        let alphabetical = db.parse_decks(DeckSortMode::Alphabetical).unwrap();
+        assert_eq!(names(alphabetical), vec!["A", "B", "C", "C::Child"]);
+    }
+
+    #[test]
+    fn test_deck_separator() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}
+             CREATE TABLE decks (id integer primary key, name text not null);
+             INSERT INTO decks VALUES (1, 'Japanese'), (2, 'Japanese/Kanji/N5');"
+        ));
+
+        let decks = db.parse_decks_with_separator(DeckSortMode::Original, Some("/")).unwrap();
+        let nested = decks.iter().find(|d| d.id == 2).unwrap();
+        assert_eq!(nested.name, "Japanese::Kanji::N5");
+        assert_eq!(nested.short_name, "N5");
+        assert_eq!(nested.parent_path(), Some("Japanese::Kanji".to_string()));
+        assert!(!nested.is_root());
+        assert!(decks.iter().find(|d| d.id == 1).unwrap().is_root());
+
+        // Without a separator the name is left alone
+        let decks = db.parse_decks(DeckSortMode::Original).unwrap();
+        assert!(decks.iter().all(|d| d.is_root()));
+        assert_eq!(decks[1].short_name, "Japanese/Kanji/N5");
+    }
+
+    #[test]
+    fn test_empty_modern_decks_falls_back_to_legacy() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}
+             CREATE TABLE decks (id integer primary key, name text not null);"
+        ));
+
+        let decks = db.parse_decks(DeckSortMode::Original).unwrap();
+        assert_eq!(decks.len(), 1);
+        assert_eq!(decks[0].name, "Default");
+    }
+
+    #[test]
+    fn test_parse_cards_clean_html() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}",
+            insert_note_sql(10, 100, 1, "<b>front</b>\x1fback")
+        ));
+
+        let raw = db.parse_cards(|_, _| {}).unwrap();
+        assert_eq!(raw[&1][0].fields[0], "<b>front</b>");
+        assert!(raw[&1][0].raw_fields.is_empty());
+
+        let options = ParseOptions { clean_html: true, ..Default::default() };
+        let cleaned = db.parse_cards_with_options(&options, |_, _| {}).unwrap();
+        let card = &cleaned[&1][0];
+        assert_eq!(card.fields[0], "front");
+        assert_eq!(card.raw_fields[0], "<b>front</b>");
+        assert_eq!(card.question.as_deref(), Some("front"));
+    }
+
+    #[test]
+    fn test_parse_cards_ordinal() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}
+             INSERT INTO cards VALUES (11, 100, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 100, 1, "front\x1fback")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let mut ordinals: Vec<(i64, u32)> = cards[&1].iter().map(|c| (c.id, c.ordinal)).collect();
+        ordinals.sort();
+        assert_eq!(ordinals, vec![(10, 0), (11, 1)]);
+    }
+
+    #[test]
+    fn test_parse_cards_scheduling() {
+        // type = 2 (review), queue = 2, ivl = 45
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}
+             INSERT INTO cards VALUES (11, 100, 1, 1, 0, 0, 2, 2, 0, 45, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 100, 1, "front\x1fback")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let review = cards[&1].iter().find(|c| c.id == 11).unwrap();
+        assert_eq!((review.card_type, review.interval), (2, 45));
+        let new = cards[&1].iter().find(|c| c.id == 10).unwrap();
+        assert_eq!((new.card_type, new.interval), (0, 0));
+        assert!(new.is_new);
+        assert!(!review.is_new);
+    }
+
+    #[test]
+    fn test_repair_mojibake() {
+        assert_eq!(repair_mojibake("cafÃ©"), "café");
+        assert_eq!(repair_mojibake("Itâ€™s naÃ¯ve"), "It’s naïve");
+        assert_eq!(repair_mojibake("ÃŸ"), "ß");
+        // Correct text is left alone
+        assert_eq!(repair_mojibake("café"), "café");
+        assert_eq!(repair_mojibake("日本語"), "日本語");
+        assert_eq!(repair_mojibake("Ã la carte"), "Ã la carte");
+    }
+
+    #[test]
+    fn test_parse_cards_repair_encoding() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}
+             INSERT INTO notes VALUES (21, 'g21', 1, 0, 0, '', CAST(X'636166E91F6F6B' AS BLOB),
+                 '', 0, 0, '');
+             INSERT INTO cards VALUES (11, 21, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "cafÃ©\x1fok")
+        ));
+        let field = |cards: &HashMap<i64, Vec<AnkiCard>>, id| {
+            cards[&1].iter().find(|c| c.id == id).unwrap().fields[0].clone()
+        };
+
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        assert_eq!(field(&cards, 10), "cafÃ©");
+        assert_eq!(field(&cards, 11), "caf\u{FFFD}");
+
+        let options = ParseOptions { repair_encoding: true, ..Default::default() };
+        let cards = db.parse_cards_with_options(&options, |_, _| {}).unwrap();
+        assert_eq!(field(&cards, 10), "café");
+        // Latin-1 bytes that aren't valid UTF-8
+        assert_eq!(field(&cards, 11), "café");
+    }
+
+    #[test]
+    fn test_parse_cards_tag_filter() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}{}{}{}
+             UPDATE notes SET tags = ' #N5 verb ' WHERE id = 20;
+             UPDATE notes SET tags = ' Lang::jp ' WHERE id = 21;
+             UPDATE notes SET tags = ' language ' WHERE id = 22;",
+            insert_note_sql(10, 20, 1, "a"),
+            insert_note_sql(11, 21, 1, "b"),
+            insert_note_sql(12, 22, 1, "c"),
+            insert_note_sql(13, 23, 1, "d"),
+        ));
+        let ids = |filter: &[&str]| {
+            let options = ParseOptions {
+                tag_filter: Some(filter.iter().map(|t| t.to_string()).collect()),
+                ..Default::default()
+            };
+            let cards = db.parse_cards_with_options(&options, |_, _| {}).unwrap();
+            let mut ids: Vec<i64> = cards.values().flatten().map(|c| c.id).collect();
+            ids.sort();
+            ids
+        };
+
+        assert_eq!(ids(&["#n5"]), vec![10]);
+        // Parent filters match subtags, but not other tags sharing the prefix
+        assert_eq!(ids(&["lang"]), vec![11]);
+        assert_eq!(ids(&["#N5", "lang::jp"]), vec![10, 11]);
+        assert!(ids(&[]).is_empty());
+        assert_eq!(db.parse_cards(|_, _| {}).unwrap()[&1].len(), 4);
+    }
+
+    #[test]
+    fn test_parse_all_tags() {
+        let legacy = database_from_sql(SCHEMA);
+        assert!(legacy.parse_all_tags().unwrap().is_empty());
+
+        let db = database_from_sql(&format!(
+            "{SCHEMA}
+             CREATE TABLE tags (tag text not null primary key, usn integer not null,
+                 collapsed boolean not null, config blob null) without rowid;
+             INSERT INTO tags VALUES ('verbs', 0, 0, NULL), ('Lang::Japanese', 0, 1, NULL),
+                 ('archive', 0, 0, NULL);"
+        ));
+        let tags = db.parse_all_tags().unwrap();
+        assert_eq!(tags, vec![
+            TagInfo { name: "archive".to_string(), expanded: true },
+            TagInfo { name: "Lang::Japanese".to_string(), expanded: false },
+            TagInfo { name: "verbs".to_string(), expanded: true },
+        ]);
+
+        let no_collapsed = database_from_sql(&format!(
+            "{SCHEMA}
+             CREATE TABLE tags (tag text not null primary key, usn integer not null) without rowid;
+             INSERT INTO tags VALUES ('verbs', 0);"
+        ));
+        assert!(no_collapsed.parse_all_tags().unwrap()[0].expanded);
+    }
+
+    #[test]
+    fn test_parse_graves() {
+        let no_graves = database_from_sql(SCHEMA);
+        assert!(no_graves.parse_graves().unwrap().is_empty());
+
+        let db = database_from_sql(&format!(
+            "{SCHEMA}
+             CREATE TABLE graves (oid integer not null, type integer not null,
+                 usn integer not null, primary key (oid, type)) without rowid;
+             INSERT INTO graves VALUES (10, 0, -1), (20, 1, -1), (5, 2, -1);"
+        ));
+        let graves = db.parse_graves().unwrap();
+        assert_eq!(graves.len(), 3);
+        assert!(graves.contains(&GraveEntry { oid: 20, type_: 1 }));
+        assert!(graves.contains(&GraveEntry { oid: 5, type_: 2 }));
+    }
+
+    #[test]
+    fn test_parse_cards_blob_deck_id() {
+        // 0x0102 = 258 as a little-endian i64
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}
+             INSERT INTO notes VALUES (20, 'guid20', 1, 0, 0, '', 'front', '', 0, 0, '');
+             INSERT INTO cards VALUES (10, 20, X'0201000000000000', 0, 0, 0, 0, 0, 0, 0, 0, 0,
+                 0, 0, 0, 0, 0, '');"
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let card = &cards[&258][0];
+        assert_eq!(card.deck_id, 258);
+        assert_eq!(card.id, 10);
+        assert_eq!(card.fields[0], "front");
+    }
+
+    #[test]
+    fn test_card_ids_by_deck() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}{}{}",
+            insert_note_sql(12, 20, 2, "a"),
+            insert_note_sql(10, 21, 2, "b"),
+            insert_note_sql(11, 22, 1, "c")
+        ));
+        let ids = db.card_ids_by_deck().unwrap();
+        assert_eq!(ids.len(), 2);
+        assert_eq!(ids[&1], vec![11]);
+        assert_eq!(ids[&2], vec![10, 12]);
+    }
+
+    #[test]
+    fn test_parse_cards_page() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}{}{}{}",
+            insert_note_sql(13, 20, 2, "d [sound:d.mp3]"),
+            insert_note_sql(11, 21, 2, "b"),
+            insert_note_sql(12, 22, 2, "c"),
+            insert_note_sql(10, 23, 1, "a")
+        ));
+        let ids = |offset, limit| -> Vec<i64> {
+            db.parse_cards_page(2, offset, limit).unwrap().iter().map(|c| c.id).collect()
+        };
+        assert_eq!(ids(0, 2), vec![11, 12]);
+        assert_eq!(ids(2, 2), vec![13]);
+        assert!(ids(4, 2).is_empty());
+
+        let page = db.parse_cards_page(2, 2, 1).unwrap();
+        assert_eq!(page[0].media_references, vec!["d.mp3"]);
+    }
+
+    #[test]
+    fn test_parse_cards_trim_empty_trailing_fields() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}{}{}",
+            insert_note_sql(10, 20, 1, "front\x1fback\x1f\x1f"),
+            insert_note_sql(11, 21, 1, "\x1fback\x1f")
+        ));
+        let fields = |options: &ParseOptions, id: i64| -> Vec<String> {
+            let cards = db.parse_cards_with_options(options, |_, _| {}).unwrap();
+            cards[&1].iter().find(|c| c.id == id).unwrap().fields.clone()
+        };
+
+        assert_eq!(fields(&ParseOptions::default(), 10), vec!["front", "back", "", ""]);
+
+        let options = ParseOptions { trim_empty_trailing_fields: true, ..Default::default() };
+        assert_eq!(fields(&options, 10), vec!["front", "back"]);
+        // Interior empties keep their position
+        assert_eq!(fields(&options, 11), vec!["", "back"]);
+    }
+
+    #[test]
+    fn test_parse_cards_order() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}
+             INSERT INTO notes VALUES (20, 'guid20', 1, 0, 0, '', 'a', '', 0, 0, '');
+             INSERT INTO cards VALUES (30, 20, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');
+             INSERT INTO cards VALUES (10, 20, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');
+             INSERT INTO cards VALUES (20, 20, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');"
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let ids: Vec<i64> = cards[&1].iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![20, 10, 30]);
+    }
+
+    #[test]
+    fn test_parse_cards_timestamps() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{COL_ROW}
+             INSERT INTO notes VALUES (20, 'guid20', 1, 1700000500, 0, '', 'front', '', 0, 0, '');
+             INSERT INTO cards VALUES (1700000000123, 20, 1, 0, 1700000900, 0, 0, 0, 0, 0, 0, 0,
+                 0, 0, 0, 0, 0, '');"
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        let card = &cards[&1][0];
+        assert_eq!(card.card_modified, 1700000900);
+        assert_eq!(card.note_modified, 1700000500);
+        assert_eq!(card.created_at(), 1700000000);
+    }
+
+    #[test]
+    fn test_parse_cards_filtered_deck() {
+        // Card 11 sits in filtered deck 5 but comes from deck 1, where it was due at 42
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{}
+             INSERT INTO cards VALUES (11, 20, 5, 0, 0, 0, 0, 0, -100000, 0, 0, 0, 0, 0, 42, 1, 0,
+                 '');",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        assert!(!cards.contains_key(&5));
+        assert_eq!(cards[&1].len(), 2);
+
+        let filtered = cards[&1].iter().find(|c| c.id == 11).unwrap();
+        assert_eq!(filtered.deck_id, 1);
+        assert_eq!(filtered.filtered_deck_id, Some(5));
+        assert_eq!(filtered.due, 42);
+        let card = cards[&1].iter().find(|c| c.id == 10).unwrap();
+        assert_eq!(card.filtered_deck_id, None);
+
+        assert_eq!(db.card_ids_by_deck().unwrap(), HashMap::from([(1, vec![10, 11])]));
+        assert_eq!(db.parse_cards_page(1, 0, 10).unwrap().len(), 2);
+        assert!(db.parse_cards_page(5, 0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_cards_missing_note() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{}
+             INSERT INTO cards VALUES (11, 999, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        assert_eq!(cards[&1].len(), 2);
+
+        let orphan = cards[&1].iter().find(|c| c.id == 11).unwrap();
+        assert!(orphan.note_missing);
+        assert!(orphan.fields.is_empty());
+        assert_eq!(orphan.note_id, 999);
+        assert_eq!(db.note_count().unwrap(), 1);
+        assert_eq!(db.card_count().unwrap(), 2);
+
+        let card = cards[&1].iter().find(|c| c.id == 10).unwrap();
+        assert!(!card.note_missing);
+    }
+
+    #[test]
+    fn test_open_path_leaves_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        Connection::open(file.path()).unwrap().execute_batch(SCHEMA).unwrap();
+
+        let db = AnkiDatabase::open_path(file.path()).unwrap();
+        assert_eq!(db.card_count().unwrap(), 0);
+        drop(db);
+
+        // Caller-owned files are not deleted on drop
+        assert!(file.path().exists());
+    }
+
+    #[test]
+    fn test_parse_cards_flag() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}{}
+             UPDATE cards SET flags = 0x0B WHERE id = 10;",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+        // Only the low bits carry the flag color (0x0B & 0b111 = 3, green)
+        assert_eq!(cards[&1][0].flag, 3);
+    }
+
+    #[test]
+    fn test_parse_cards_queue_state() {
+        let mut sql = SCHEMA.to_string();
+        for (i, queue) in [-3, -2, -1, 0, 1, 2, 3, 4].iter().enumerate() {
+            let id = i as i64 + 10;
+            sql.push_str(&insert_note_sql(id, id, 1, "front"));
+            sql.push_str(&format!("UPDATE cards SET queue = {queue} WHERE id = {id};"));
+        }
+        let db = database_from_sql(&sql);
+        let cards = db.parse_cards(|_, _| {}).unwrap();
+
+        for card in &cards[&1] {
+            assert_eq!(card.is_suspended, card.queue == -1, "queue {}", card.queue);
+            assert_eq!(card.is_buried, matches!(card.queue, -2 | -3), "queue {}", card.queue);
+        }
+        assert_eq!(cards[&1].iter().filter(|c| c.is_suspended).count(), 1);
+        assert_eq!(cards[&1].iter().filter(|c| c.is_buried).count(), 2);
+    }
+
+    #[test]
+    fn test_parse_collection_meta_legacy() {
+        let db = database_from_sql(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 1600000000, 0, 0, 11, 0, 0, 0,
+                '{{"schedVer": 2, "rollover": 5, "creationOffset": -540}}',
+                '{{}}', '{{}}', '{{}}', '{{}}');"#
+        ));
+        let meta = db.parse_collection_meta().unwrap();
+        assert_eq!(meta, CollectionMeta {
+            created_at: 1600000000,
+            scheduler_version: 2,
+            rollover_hour: 5,
+            creation_offset: Some(-540),
+        });
+    }
+
+    #[test]
+    fn test_parse_collection_meta_modern() {
+        let db = database_from_sql(&format!(
+            "{SCHEMA}
+            INSERT INTO col VALUES (1, 1600000000, 0, 0, 18, 0, 0, 0, '', '', '', '', '');
+            CREATE TABLE config (KEY text primary key, usn integer, mtime_secs integer, val blob);
+            INSERT INTO config VALUES ('schedVer', 0, 0, CAST('3' AS BLOB));"
+        ));
+        let meta = db.parse_collection_meta().unwrap();
+        assert_eq!(meta.scheduler_version, 3);
+        // Absent keys fall back to Anki's defaults
+        assert_eq!(meta.rollover_hour, 4);
+        assert_eq!(meta.creation_offset, None);
+    }
+
+    #[test]
+    fn test_open_from_bytes_unwritable_temp_dir() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        {
+            let conn = Connection::open(file.path()).unwrap();
+            // WAL-mode files need their header patched to load into memory
+            conn.pragma_update(None, "journal_mode", "WAL").unwrap();
+            conn.execute_batch(&format!("{SCHEMA}{COL_ROW}{}", insert_note_sql(10, 20, 1, "front")))
+                .unwrap();
+        }
+        let data = std::fs::read(file.path()).unwrap();
+        assert_eq!(data[18..20], [2, 2]);
+
+        // A directory that doesn't exist stands in for an unwritable one
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing");
+        let db = AnkiDatabase::open_from_bytes_in(&data, &missing).unwrap();
+        assert!(db._temp_path.is_none());
+        assert_eq!(db.parse_cards(|_, _| {}).unwrap()[&1][0].fields, vec!["front"]);
+        assert!(!missing.exists());
+    }
+
+    #[test]
+    fn test_invalid_database_header() {
+        assert!(matches!(
+            AnkiDatabase::open_from_bytes(b"definitely not sqlite"),
+            Err(AnkiError::InvalidDatabaseHeader)
+        ));
+        // Truncated before the end of the header
+        assert!(matches!(
+            AnkiDatabase::open_from_bytes(b"SQLite for"),
+            Err(AnkiError::InvalidDatabaseHeader)
+        ));
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), b"garbage").unwrap();
+        assert!(matches!(
+            AnkiDatabase::open_path(file.path()),
+            Err(AnkiError::InvalidDatabaseHeader)
+        ));
     }
 }
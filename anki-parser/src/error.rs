@@ -1,3 +1,5 @@
+use std::time::Instant;
+
 use thiserror::Error;
 
 /// Error types that can occur during Anki file parsing
@@ -6,9 +8,18 @@ pub enum AnkiError {
     #[error("File not found: {0}")]
     FileNotFound(String),
 
-    #[error("Invalid archive format")]
+    #[error("Not a valid Anki archive (corrupt zip or no collection inside)")]
     InvalidArchive,
 
+    #[error("Archive is password-protected")]
+    EncryptedArchive,
+
+    #[error("Unsupported archive compression: {0}")]
+    UnsupportedCompression(String),
+
+    #[error("Not a valid SQLite database (bad header, possibly truncated or corrupt)")]
+    InvalidDatabaseHeader,
+
     #[error("Database error: {0}")]
     DatabaseError(String),
 
@@ -23,6 +34,23 @@ pub enum AnkiError {
 
     #[error("I/O error: {0}")]
     IoError(String),
+
+    #[error("Parsing timed out")]
+    Timeout,
+
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("Unsupported schema version {found} (newest supported: {supported_max})")]
+    UnsupportedSchemaVersion { found: u32, supported_max: u32 },
+}
+
+/// Fail with `AnkiError::Timeout` once `deadline` has passed
+pub(crate) fn check_deadline(deadline: Option<Instant>) -> Result<(), AnkiError> {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Err(AnkiError::Timeout),
+        _ => Ok(()),
+    }
 }
 
 impl From<std::io::Error> for AnkiError {
@@ -37,9 +65,22 @@ impl From<std::io::Error> for AnkiError {
 
 impl From<zip::result::ZipError> for AnkiError {
     fn from(e: zip::result::ZipError) -> Self {
+        use zip::result::ZipError;
+
         match e {
-            zip::result::ZipError::FileNotFound => AnkiError::InvalidArchive,
-            zip::result::ZipError::InvalidArchive(_) => AnkiError::InvalidArchive,
+            ZipError::FileNotFound => AnkiError::InvalidArchive,
+            ZipError::InvalidArchive(_) => AnkiError::InvalidArchive,
+            ZipError::InvalidPassword => AnkiError::EncryptedArchive,
+            ZipError::UnsupportedArchive(detail) => {
+                let lower = detail.to_lowercase();
+                if detail == ZipError::PASSWORD_REQUIRED || lower.contains("encrypt") {
+                    AnkiError::EncryptedArchive
+                } else if lower.contains("compression") {
+                    AnkiError::UnsupportedCompression(detail.to_string())
+                } else {
+                    AnkiError::InvalidArchive
+                }
+            }
             _ => AnkiError::IoError(e.to_string()),
         }
     }
@@ -51,8 +92,45 @@ impl From<rusqlite::Error> for AnkiError {
     }
 }
 
+/// Attach the operation that was running to SQLite errors
+pub(crate) trait DatabaseContext<T> {
+    fn context(self, operation: &str) -> Result<T, AnkiError>;
+}
+
+impl<T> DatabaseContext<T> for Result<T, rusqlite::Error> {
+    fn context(self, operation: &str) -> Result<T, AnkiError> {
+        self.map_err(|e| AnkiError::DatabaseError(format!("while {}: {}", operation, e)))
+    }
+}
+
 impl From<serde_json::Error> for AnkiError {
     fn from(e: serde_json::Error) -> Self {
         AnkiError::JsonError(e.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zip::result::ZipError;
+
+    #[test]
+    fn test_zip_error_mapping() {
+        assert!(matches!(
+            AnkiError::from(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)),
+            AnkiError::EncryptedArchive
+        ));
+        assert!(matches!(
+            AnkiError::from(ZipError::InvalidPassword),
+            AnkiError::EncryptedArchive
+        ));
+        assert!(matches!(
+            AnkiError::from(ZipError::UnsupportedArchive("Compression method not supported")),
+            AnkiError::UnsupportedCompression(_)
+        ));
+        assert!(matches!(
+            AnkiError::from(ZipError::InvalidArchive("Invalid zip header")),
+            AnkiError::InvalidArchive
+        ));
+    }
+}
@@ -0,0 +1,469 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+use serde_json::{json, Value};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::database::{join_fields, unique_temp_path};
+use crate::error::{AnkiError, DatabaseContext};
+use crate::html::clean_html;
+use crate::models::{AnkiCard, AnkiCollection, AnkiNoteType};
+
+const WRITING_COLLECTION: &str = "writing collection";
+
+/// Anki's built-in "Default" deck, which every collection must contain
+const DEFAULT_DECK_ID: i64 = 1;
+
+/// Starting ease factor (permille) for review cards
+const DEFAULT_EASE_FACTOR: i32 = 2500;
+
+/// Legacy (schema 11) tables and indexes, as created by Anki
+const SCHEMA: &str = "
+    CREATE TABLE col (id integer primary key, crt integer not null, mod integer not null,
+        scm integer not null, ver integer not null, dty integer not null, usn integer not null,
+        ls integer not null, conf text not null, models text not null, decks text not null,
+        dconf text not null, tags text not null);
+    CREATE TABLE notes (id integer primary key, guid text not null, mid integer not null,
+        mod integer not null, usn integer not null, tags text not null, flds text not null,
+        sfld integer not null, csum integer not null, flags integer not null, data text not null);
+    CREATE TABLE cards (id integer primary key, nid integer not null, did integer not null,
+        ord integer not null, mod integer not null, usn integer not null, type integer not null,
+        queue integer not null, due integer not null, ivl integer not null, factor integer not null,
+        reps integer not null, lapses integer not null, left integer not null, odue integer not null,
+        odid integer not null, flags integer not null, data text not null);
+    CREATE TABLE revlog (id integer primary key, cid integer not null, usn integer not null,
+        ease integer not null, ivl integer not null, lastIvl integer not null,
+        factor integer not null, time integer not null, type integer not null);
+    CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+    CREATE INDEX ix_notes_usn ON notes (usn);
+    CREATE INDEX ix_cards_usn ON cards (usn);
+    CREATE INDEX ix_revlog_usn ON revlog (usn);
+    CREATE INDEX ix_cards_nid ON cards (nid);
+    CREATE INDEX ix_cards_sched ON cards (did, queue, due);
+    CREATE INDEX ix_revlog_cid ON revlog (cid);
+    CREATE INDEX ix_notes_csum ON notes (csum);
+";
+
+/// Write a collection to a legacy-format .apkg that Anki can import
+///
+/// Builds a `collection.anki2` database with the collection's decks, note
+/// types, notes and cards, and zips it with the media files and a JSON `media`
+/// mapping. Fields are written from `AnkiCard.raw_fields` when the cards were
+/// cleaned, otherwise from `AnkiCard.fields`.
+///
+/// Cards whose note was missing are skipped. Note types that weren't parsed are
+/// replaced with a generic one so the notes still import. Review history isn't
+/// held, so review cards keep their type and interval but are due immediately.
+pub fn write_apkg(collection: &AnkiCollection, path: String) -> Result<(), AnkiError> {
+    let path = Path::new(&path);
+    let db_path = unique_temp_path(&std::env::temp_dir());
+    // The archive is built beside `path` and renamed over it once complete,
+    // so a failed export leaves any existing file there untouched
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+    let archive_path = unique_temp_path(dir.unwrap_or(Path::new(".")));
+
+    let result = write_collection_db(collection, &db_path)
+        .and_then(|()| write_archive(collection, &db_path, &archive_path))
+        .and_then(|()| Ok(std::fs::rename(&archive_path, path)?));
+
+    let _ = std::fs::remove_file(&db_path);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&archive_path);
+    }
+    result
+}
+
+/// Zip the collection database and media into `path`
+fn write_archive(
+    collection: &AnkiCollection,
+    db_path: &Path,
+    path: &Path,
+) -> Result<(), AnkiError> {
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut zip = ZipWriter::new(BufWriter::new(File::create(path)?));
+
+    zip.start_file("collection.anki2", options)?;
+    std::io::copy(&mut File::open(db_path)?, &mut zip)?;
+
+    // Media entries are numbered; the mapping gives each number its filename
+    let mut mapping = BTreeMap::new();
+    for filename in collection.media.filenames() {
+        if let Some(data) = collection.media.data_for(filename.clone()) {
+            let index = mapping.len().to_string();
+            zip.start_file(index.as_str(), options)?;
+            zip.write_all(&data)?;
+            mapping.insert(index, filename);
+        }
+    }
+
+    zip.start_file("media", options)?;
+    zip.write_all(serde_json::to_string(&mapping)?.as_bytes())?;
+
+    zip.finish()?.flush()?;
+    Ok(())
+}
+
+/// Create the legacy collection database at `db_path`
+fn write_collection_db(collection: &AnkiCollection, db_path: &Path) -> Result<(), AnkiError> {
+    let mut conn = Connection::open(db_path).context(WRITING_COLLECTION)?;
+    let tx = conn.transaction().context(WRITING_COLLECTION)?;
+    tx.execute_batch(SCHEMA).context(WRITING_COLLECTION)?;
+
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64);
+    let now_secs = now_ms / 1000;
+
+    // Notes are shared between their cards; write each once
+    let mut notes: BTreeMap<i64, &AnkiCard> = BTreeMap::new();
+    let mut cards: Vec<&AnkiCard> = collection.cards().filter(|c| !c.note_missing).collect();
+    cards.sort_by_key(|c| c.id);
+    for card in &cards {
+        notes.entry(card.note_id).or_insert(card);
+    }
+
+    let models = models_json(collection, notes.values().copied());
+    let conf = json!({
+        "nextPos": notes.len() + 1,
+        "schedVer": collection.meta.scheduler_version,
+        "rollover": collection.meta.rollover_hour,
+        "creationOffset": collection.meta.creation_offset,
+    });
+    let dconf = json!({"1": {"id": 1, "name": "Default", "mod": 0, "usn": 0}});
+
+    tx.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![
+            collection.meta.created_at,
+            now_ms,
+            conf.to_string(),
+            models.to_string(),
+            decks_json(collection).to_string(),
+            dconf.to_string(),
+        ],
+    )
+    .context(WRITING_COLLECTION)?;
+
+    // New cards are due in the order their notes were added
+    let mut positions = HashMap::new();
+    for (position, (note_id, card)) in notes.iter().enumerate() {
+        let fields = card_fields(card);
+        tx.execute(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, 0, ?5, ?6, ?7, ?8, 0, '')",
+            params![
+                note_id,
+                card.note_guid,
+                card.note_type_id,
+                now_secs,
+                tags_column(&card.tags),
+                join_fields(fields),
+                card.sort_field,
+                field_checksum(fields.first().map_or("", |f| f.as_str())),
+            ],
+        )
+        .context(WRITING_COLLECTION)?;
+        positions.insert(*note_id, position as i64 + 1);
+    }
+
+    for card in &cards {
+        let due = if card.card_type == 0 { positions[&card.note_id] } else { 0 };
+        let factor = if matches!(card.card_type, 2 | 3) { DEFAULT_EASE_FACTOR } else { 0 };
+        tx.execute(
+            "INSERT INTO cards
+             VALUES (?1, ?2, ?3, ?4, ?5, 0, ?6, ?7, ?8, ?9, ?10, 0, 0, 0, 0, 0, ?11, '')",
+            params![
+                card.id,
+                card.note_id,
+                card.deck_id,
+                card.ordinal,
+                now_secs,
+                card.card_type,
+                card.queue,
+                due,
+                card.interval,
+                factor,
+                card.flag,
+            ],
+        )
+        .context(WRITING_COLLECTION)?;
+    }
+
+    tx.commit().context(WRITING_COLLECTION)
+}
+
+/// Anki's `notes.tags` format: space-separated with a leading and trailing space
+fn tags_column(tags: &[String]) -> String {
+    if tags.is_empty() {
+        String::new()
+    } else {
+        format!(" {} ", tags.join(" "))
+    }
+}
+
+/// Cards as tab-separated text: a header row, then one row per card
+///
+/// Columns are the deck name, the space-separated tags, and each field as
+/// cleaned text. Values containing tabs, newlines or quotes are quoted, with
+/// inner quotes doubled. `deck_id` limits the export to one deck.
+pub fn cards_tsv(collection: &AnkiCollection, deck_id: Option<i64>) -> String {
+    let deck_names: HashMap<i64, &str> =
+        collection.decks.iter().map(|d| (d.id, d.name.as_str())).collect();
+    let cards: Vec<&AnkiCard> = collection
+        .cards()
+        .filter(|c| deck_id.is_none_or(|id| c.deck_id == id))
+        .collect();
+
+    let field_count = cards.iter().map(|c| c.fields.len()).max().unwrap_or(0);
+    let mut header = vec!["Deck".to_string(), "Tags".to_string()];
+    header.extend((1..=field_count).map(|i| format!("Field {}", i)));
+
+    let mut out = header.join("\t");
+    out.push('\n');
+    for card in cards {
+        let mut row = vec![
+            tsv_escape(deck_names.get(&card.deck_id).copied().unwrap_or_default()),
+            tsv_escape(&card.tags.join(" ")),
+        ];
+        row.extend(card.fields.iter().map(|field| {
+            if card.raw_fields.is_empty() {
+                tsv_escape(&clean_html(field))
+            } else {
+                tsv_escape(field)
+            }
+        }));
+        // Pad so every row has a column per field
+        row.resize(field_count + 2, String::new());
+        out.push_str(&row.join("\t"));
+        out.push('\n');
+    }
+    out
+}
+
+/// Quote a TSV value if it contains a tab, newline or quote
+fn tsv_escape(value: &str) -> String {
+    if value.contains(['\t', '\n', '\r', '"']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The HTML to store for a card's note fields
+fn card_fields(card: &AnkiCard) -> &[String] {
+    if card.raw_fields.is_empty() {
+        &card.fields
+    } else {
+        &card.raw_fields
+    }
+}
+
+/// Anki's first-field checksum: the first 8 hex digits of the SHA-1 of the
+/// field's text, used to detect duplicate notes
+fn field_checksum(field: &str) -> i64 {
+    let digest = sha1_smol::Sha1::from(clean_html(field)).digest().bytes();
+    i64::from(u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]))
+}
+
+/// Legacy `col.decks` JSON, including the Default deck Anki requires
+fn decks_json(collection: &AnkiCollection) -> Value {
+    let mut decks: Vec<(i64, &str)> =
+        collection.decks.iter().map(|d| (d.id, d.name.as_str())).collect();
+    if !decks.iter().any(|(id, _)| *id == DEFAULT_DECK_ID) {
+        decks.push((DEFAULT_DECK_ID, "Default"));
+    }
+
+    let decks = decks
+        .into_iter()
+        .map(|(id, name)| {
+            let deck = json!({
+                "id": id, "name": name, "mod": 0, "usn": 0, "desc": "", "dyn": 0, "conf": 1,
+                "collapsed": false, "newToday": [0, 0], "revToday": [0, 0],
+                "lrnToday": [0, 0], "timeToday": [0, 0], "extendNew": 0, "extendRev": 0,
+            });
+            (id.to_string(), deck)
+        })
+        .collect();
+    Value::Object(decks)
+}
+
+/// Legacy `col.models` JSON for the collection's note types
+///
+/// Notes whose note type is unknown get a generic one with enough fields.
+fn models_json<'a>(
+    collection: &AnkiCollection,
+    notes: impl Iterator<Item = &'a AnkiCard>,
+) -> Value {
+    let mut note_types: BTreeMap<i64, AnkiNoteType> =
+        collection.note_types.iter().map(|nt| (nt.id, nt.clone())).collect();
+
+    for card in notes {
+        let field_count = card_fields(card).len().max(1);
+        let note_type = note_types.entry(card.note_type_id).or_insert_with(|| AnkiNoteType {
+            id: card.note_type_id,
+            name: format!("Note type {}", card.note_type_id),
+            field_names: Vec::new(),
+            templates: Vec::new(),
+            css: String::new(),
+            is_cloze: false,
+        });
+        // Pad field names so every note's fields are covered
+        while note_type.field_names.len() < field_count {
+            note_type.field_names.push(format!("Field {}", note_type.field_names.len() + 1));
+        }
+    }
+
+    let models = note_types
+        .into_values()
+        .map(|nt| (nt.id.to_string(), model_json(&nt)))
+        .collect();
+    Value::Object(models)
+}
+
+/// A single legacy note type ("model")
+fn model_json(note_type: &AnkiNoteType) -> Value {
+    let mut templates: Vec<Value> = note_type
+        .templates
+        .iter()
+        .map(|t| {
+            json!({
+                "name": t.name, "ord": t.ordinal, "qfmt": t.question_format,
+                "afmt": t.answer_format, "bqfmt": "", "bafmt": "", "did": null,
+            })
+        })
+        .collect();
+    if templates.is_empty() {
+        // Show the first field, with the rest on the back
+        let first = note_type.field_names.first().map_or("", |f| f.as_str());
+        let back: Vec<String> =
+            note_type.field_names.iter().skip(1).map(|f| format!("{{{{{}}}}}", f)).collect();
+        templates.push(json!({
+            "name": "Card 1", "ord": 0, "qfmt": format!("{{{{{}}}}}", first),
+            "afmt": format!("{{{{FrontSide}}}}<hr id=answer>{}", back.join("<br>")),
+            "bqfmt": "", "bafmt": "", "did": null,
+        }));
+    }
+
+    let fields: Vec<Value> = note_type
+        .field_names
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({
+                "name": name, "ord": ord, "sticky": false, "rtl": false,
+                "font": "Arial", "size": 20, "media": [],
+            })
+        })
+        .collect();
+
+    json!({
+        "id": note_type.id, "name": note_type.name, "type": if note_type.is_cloze { 1 } else { 0 },
+        "mod": 0, "usn": 0, "sortf": 0, "did": DEFAULT_DECK_ID, "tmpls": templates,
+        "flds": fields, "css": note_type.css, "latexPre": "", "latexPost": "", "tags": [], "vers": [],
+        "req": [[0, "any", [0]]],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::tests::{insert_note_sql, COL_ROW, SCHEMA as FIXTURE_SCHEMA};
+    use crate::models::{AnkiProgress, AnkiProgressCallback, ParseOptions};
+    use crate::tests::build_apkg;
+
+    struct NoProgress;
+
+    impl AnkiProgressCallback for NoProgress {
+        fn on_progress(&self, _progress: AnkiProgress) {}
+    }
+
+    fn parse(path: &Path, options: ParseOptions) -> AnkiCollection {
+        crate::parse_anki_file_with_options(
+            path.display().to_string(),
+            options,
+            Box::new(NoProgress),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_write_apkg_round_trip() {
+        let apkg = build_apkg(&format!(
+            "{FIXTURE_SCHEMA}{COL_ROW}{}{}",
+            insert_note_sql(10, 20, 1, "<b>front</b>\x1fback"),
+            insert_note_sql(11, 21, 1, "second\x1fother"),
+        ));
+        let options = ParseOptions { clean_html: true, ..Default::default() };
+        let original = parse(apkg.path(), options.clone());
+        original.media.insert("a.mp3".to_string(), b"ID3audio".to_vec());
+
+        let out = tempfile::NamedTempFile::new().unwrap();
+        write_apkg(&original, out.path().display().to_string()).unwrap();
+        let exported = parse(out.path(), options);
+
+        assert_eq!(exported.total_card_count(), 2);
+        let card = exported.cards().find(|c| c.id == 10).unwrap();
+        assert_eq!(card.raw_fields, vec!["<b>front</b>", "back"]);
+        assert_eq!(card.note_guid, "guid20");
+        assert_eq!(card.question.as_deref(), Some("front"));
+        assert_eq!(exported.media.data_for("a.mp3".to_string()).unwrap(), b"ID3audio");
+        assert_eq!(exported.note_types[0].name, "Basic");
+    }
+
+    #[test]
+    fn test_write_apkg_failure_keeps_existing_path() {
+        let apkg = build_apkg(&format!("{FIXTURE_SCHEMA}{COL_ROW}"));
+        let collection = parse(apkg.path(), ParseOptions::default());
+
+        // A non-empty directory can't be replaced, so the final rename fails
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("deck.apkg");
+        std::fs::create_dir(&target).unwrap();
+        std::fs::write(target.join("keep.txt"), b"mine").unwrap();
+
+        assert!(write_apkg(&collection, target.display().to_string()).is_err());
+        assert_eq!(std::fs::read(target.join("keep.txt")).unwrap(), b"mine");
+        // The half-built archive is cleaned up
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_cards_tsv() {
+        let apkg = build_apkg(&format!(
+            "{FIXTURE_SCHEMA}{COL_ROW}{}{}
+             UPDATE notes SET tags = ' vocab verb ' WHERE id = 20;
+             INSERT INTO notes VALUES (22, 'g22', 1, 0, 0, '', 'only', '', 0, 0, '');
+             INSERT INTO cards VALUES (12, 22, 5, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "<b>front</b>\x1fline one<br>line \"two\""),
+            insert_note_sql(11, 21, 1, "a\tb\x1fback"),
+        ));
+        let collection = parse(apkg.path(), ParseOptions::default());
+
+        let tsv = cards_tsv(&collection, Some(1));
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines[0], "Deck\tTags\tField 1\tField 2");
+        assert_eq!(lines[1], "Default\tvocab verb\tfront\t\"line one");
+        assert_eq!(lines[2], "line \"\"two\"\"\"");
+        assert_eq!(lines[3], "Default\t\t\"a\tb\"\tback");
+        assert_eq!(lines.len(), 4);
+
+        // Every deck, padding short rows
+        let tsv = cards_tsv(&collection, None);
+        assert!(tsv.ends_with("Deck 5\t\tonly\t\n"));
+    }
+
+    #[test]
+    fn test_field_checksum() {
+        // Markup is stripped before hashing
+        let expected = i64::from_str_radix(
+            &sha1_smol::Sha1::from("front").digest().to_string()[..8],
+            16,
+        )
+        .unwrap();
+        assert_eq!(field_checksum("<b>front</b>"), expected);
+    }
+}
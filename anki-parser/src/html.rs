@@ -1,4 +1,61 @@
+use std::sync::LazyLock;
+
 use regex::Regex;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::error::AnkiError;
+
+// Fixed patterns, compiled once on first use. They are constants, so the
+// unwraps can only fail if a pattern here is edited into an invalid one,
+// which the tests below would catch
+pub(crate) static SOUND_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(SOUND_PATTERN).unwrap());
+/// `<img src="...">`, with the filename in group 1
+pub(crate) static IMG_SRC_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<img[^>]+src=["']?([^"'\s>]+)["']?"#).unwrap());
+/// A whole `<img>` tag, with the filename in group 1
+static IMG_TAG_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r#"<img[^>]+src=["']?([^"'\s>]+)["']?[^>]*>"#).unwrap());
+static BLOCK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)</?(?:div|p)(?:\s[^>]*)?>").unwrap());
+static BOUNDARY_RUN_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!("[ \t\r\n]*(?:{}[ \t\r\n]*)+", BLOCK_BOUNDARY)).unwrap()
+});
+static BR_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<br\s*/?>").unwrap());
+static HR_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)<hr\b[^>]*>").unwrap());
+static SPAN_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"</?span[^>]*>").unwrap());
+static FORMAT_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"</?(?:b|i|u|strong|em|font|a)[^>]*>").unwrap());
+static TAG_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<[^>]+>").unwrap());
+/// Two or more spaces, counting no-break spaces
+static SPACE_RUN_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"[ \u{A0}]{2,}").unwrap());
+static MULTI_NEWLINE_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\n{3,}").unwrap());
+static TEMPLATE_MARKER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{(?:type:[^}]*|[#/^][^}]*)\}\}").unwrap());
+static PRE_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<pre[^>]*>(.*?)</pre>").unwrap());
+/// `<hN>` heading with its level in group 1 and content in group 2
+static HEADING_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<h([1-6])(?:\s[^>]*)?>(.*?)</h[1-6]\s*>").unwrap());
+/// Opening or closing `<blockquote>` tag
+static BLOCKQUOTE_TAG_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)</?blockquote\b[^>]*>").unwrap());
+static PROTECTED_PLACEHOLDER_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!("{}(\\d+){}", PROTECTED_START, PROTECTED_END)).unwrap()
+});
+static LINK_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(&format!("{}(\\d+){}", LINK_START, LINK_END)).unwrap());
+static LI_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<li[^>]*>(.*?)(?:</li>|$)").unwrap());
+static OL_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?is)<ol[^>]*>(.*?)</ol>").unwrap());
+static UL_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?is)</?ul[^>]*>").unwrap());
+static DECIMAL_ENTITY_REGEX: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"&#(\d+);").unwrap());
+static HEX_ENTITY_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"&#[xX]([0-9a-fA-F]+);").unwrap());
+static MEDIA_LINK_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\]\(media:((?:[^()]|\([^()]*\))*)\)").unwrap());
 
 /// Convert HTML field content to Markdown-like text
 ///
@@ -6,77 +63,270 @@ use regex::Regex;
 /// - [sound:filename.mp3] → [🔊 filename.mp3](media:filename.mp3)
 /// - <img src="filename.jpg"> → ![filename.jpg](media:filename.jpg)
 /// - <br>, <br/> → \n
-/// - <div>, </div>, <p>, </p> → \n
+/// - <div>/<p> boundaries → one \n each, however deeply nested
+/// - <pre> → fenced code block, contents kept verbatim
+/// - <ul>/<ol> items → "- item" / "1. item", one per line
+/// - <blockquote> → lines prefixed with "> " ("> > " when nested)
+/// - <h1>…<h6> → "# heading" … "###### heading", followed by a blank line
+/// - <hr> → "---" thematic break between blank lines
 /// - <span>, <b>, <i>, <u>, <strong>, <em> → removed
+/// - Leaked template markers ({{type:Field}}, {{#Field}}, {{/Field}}, {{^Field}}) → removed
 /// - HTML entities → decoded
-/// - Multiple newlines → normalized
+/// - Multiple newlines → normalized (outside code blocks)
 ///
 /// The `media:` prefix is a placeholder that Swift will replace with actual saegim:// URLs
 pub fn clean_html(html: &str) -> String {
-    let mut text = html.to_string();
+    clean_html_with(html, &HtmlOptions::default())
+}
+
+/// Options for `clean_html_with`; the default matches `clean_html`
+#[derive(Debug, Clone, Default)]
+pub struct HtmlOptions {
+    /// Extra sound tags to convert to audio links, see `compile_sound_patterns`
+    pub sound_patterns: Vec<Regex>,
+    /// Collapse runs of spaces (including decoded `&nbsp;`) into one, outside code blocks
+    pub collapse_spaces: bool,
+}
+
+/// `clean_html` with extra options, see `HtmlOptions`
+pub fn clean_html_with(html: &str, options: &HtmlOptions) -> String {
+    // Pull quotes (cleaned on their own) and code blocks out first so no
+    // later pass rewrites their contents
+    let mut protected = Vec::new();
+    let text = protect_blockquotes(html, options, &mut protected);
+    let mut text = protect_code_blocks(&text, &mut protected);
+
+    text = strip_template_markers(&text);
+
+    // Media links are set aside like code blocks: their filenames are decoded
+    // here, and must not be decoded a second time with the rest of the text
+    let mut links = Vec::new();
 
     // Convert Anki sound references [sound:filename.mp3] to markdown audio
     // Using media: prefix as placeholder for Swift to replace
-    let sound_regex = Regex::new(r"\[sound:([^\]]+)\]").unwrap();
-    text = sound_regex
-        .replace_all(&text, |caps: &regex::Captures| {
-            let filename = &caps[1];
-            format!("[🔊 {}](media:{})", filename, filename)
-        })
-        .to_string();
+    for regex in std::iter::once(&*SOUND_REGEX).chain(&options.sound_patterns) {
+        text = regex
+            .replace_all(&text, |caps: &regex::Captures| {
+                let filename = decode_html_entities(&unescape_sound_filename(&caps[1]));
+                links.push(format!("[🔊 {}](media:{})", filename, filename));
+                format!("{}{}{}", LINK_START, links.len() - 1, LINK_END)
+            })
+            .to_string();
+    }
 
     // Convert <img src="filename"> to markdown image
-    let img_regex = Regex::new(r#"<img[^>]+src=["']?([^"'\s>]+)["']?[^>]*>"#).unwrap();
-    text = img_regex
+    text = IMG_TAG_REGEX
         .replace_all(&text, |caps: &regex::Captures| {
-            let filename = &caps[1];
-            format!("![{}](media:{})", filename, filename)
+            let filename = decode_html_entities(&caps[1]);
+            links.push(format!("![{}](media:{})", filename, filename));
+            format!("{}{}{}", LINK_START, links.len() - 1, LINK_END)
         })
         .to_string();
 
-    // Replace <br>, <br/>, <br /> with newlines
-    let br_regex = Regex::new(r"<br\s*/?>").unwrap();
-    text = br_regex.replace_all(&text, "\n").to_string();
-
-    // Replace block elements with newlines
-    let div_open_regex = Regex::new(r"<div[^>]*>").unwrap();
-    text = div_open_regex.replace_all(&text, "").to_string();
+    // Mark <div>/<p> boundaries, then merge each run of boundaries (and the
+    // source whitespace around them) into one, so nested or empty blocks
+    // don't stack up newlines
+    text = BLOCK_REGEX.replace_all(&text, BLOCK_BOUNDARY.to_string()).to_string();
+    text = BOUNDARY_RUN_REGEX.replace_all(&text, BLOCK_BOUNDARY.to_string()).to_string();
 
-    let div_close_regex = Regex::new(r"</div>").unwrap();
-    text = div_close_regex.replace_all(&text, "\n").to_string();
+    // Replace <br>, <br/>, <br /> with newlines
+    text = BR_REGEX.replace_all(&text, "\n").to_string();
+    text = HR_REGEX.replace_all(&text, "\n\n---\n\n").to_string();
 
-    let p_open_regex = Regex::new(r"<p[^>]*>").unwrap();
-    text = p_open_regex.replace_all(&text, "").to_string();
+    text = convert_headings(&text);
+    text = convert_lists(&text);
 
-    let p_close_regex = Regex::new(r"</p>").unwrap();
-    text = p_close_regex.replace_all(&text, "\n").to_string();
+    // Each block boundary is exactly one newline; code blocks get a blank line either side
+    text = text.replace(BLOCK_BOUNDARY, "\n");
+    text = text
+        .replace(PROTECTED_START, &format!("\n\n{}", PROTECTED_START))
+        .replace(PROTECTED_END, &format!("{}\n\n", PROTECTED_END));
 
     // Remove inline formatting tags
-    let span_regex = Regex::new(r"</?span[^>]*>").unwrap();
-    text = span_regex.replace_all(&text, "").to_string();
-
-    let format_regex = Regex::new(r"</?(?:b|i|u|strong|em|font|a)[^>]*>").unwrap();
-    text = format_regex.replace_all(&text, "").to_string();
+    text = SPAN_REGEX.replace_all(&text, "").to_string();
+    text = FORMAT_REGEX.replace_all(&text, "").to_string();
 
     // Remove any remaining HTML tags
-    let tag_regex = Regex::new(r"<[^>]+>").unwrap();
-    text = tag_regex.replace_all(&text, "").to_string();
+    text = TAG_REGEX.replace_all(&text, "").to_string();
 
     // Decode HTML entities
     text = decode_html_entities(&text);
 
+    // Code blocks are still placeholders here, so their indentation survives
+    if options.collapse_spaces {
+        text = SPACE_RUN_REGEX.replace_all(&text, " ").to_string();
+    }
+
     // Normalize whitespace
     text = text.trim().to_string();
 
     // Collapse multiple newlines into at most two
-    let multi_newline_regex = Regex::new(r"\n{3,}").unwrap();
-    text = multi_newline_regex.replace_all(&text, "\n\n").to_string();
+    text = MULTI_NEWLINE_REGEX.replace_all(&text, "\n\n").to_string();
+
+    text = restore_placeholders(&text, &LINK_PLACEHOLDER_REGEX, &links);
+    restore_placeholders(&text, &PROTECTED_PLACEHOLDER_REGEX, &protected)
+}
+
+/// Anki sound tag, or the `[audio:...]` form some add-ons use; backslash-escaped
+/// brackets may appear inside the filename
+pub(crate) const SOUND_PATTERN: &str = r"\[(?:sound|audio):((?:\\.|[^\]\\])+)\]";
+
+/// Compile extra sound-tag patterns, e.g. `\[tts:([^\]]+)\]`
+/// Each pattern's first capture group must hold the filename
+pub fn compile_sound_patterns(patterns: &[String]) -> Result<Vec<Regex>, AnkiError> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let regex = Regex::new(pattern)
+                .map_err(|e| AnkiError::InvalidPattern(format!("{}: {}", pattern, e)))?;
+            if regex.captures_len() < 2 {
+                return Err(AnkiError::InvalidPattern(format!(
+                    "{}: needs a capture group for the filename",
+                    pattern
+                )));
+            }
+            Ok(regex)
+        })
+        .collect()
+}
+
+/// Undo backslash escapes in a filename captured by `SOUND_PATTERN`
+pub(crate) fn unescape_sound_filename(filename: &str) -> String {
+    let mut result = String::with_capacity(filename.len());
+    let mut chars = filename.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            _ => result.push(c),
+        }
+    }
+    result
+}
 
+/// Remove template fragments that leaked into field text
+/// Cloze deletions ({{c1::...}}) are left alone
+fn strip_template_markers(text: &str) -> String {
+    TEMPLATE_MARKER_REGEX.replace_all(text, "").to_string()
+}
+
+/// Delimiters for code block placeholders; private-use characters never appear in real fields
+const PROTECTED_START: char = '\u{E000}';
+const PROTECTED_END: char = '\u{E001}';
+/// Stand-in for a <div>/<p> boundary until runs of them are merged
+const BLOCK_BOUNDARY: char = '\u{E002}';
+/// Delimiters for media link placeholders
+const LINK_START: char = '\u{E003}';
+const LINK_END: char = '\u{E004}';
+
+/// Replace each outermost <blockquote> with a placeholder, pushing its cleaned,
+/// "> "-prefixed text onto `blocks`. An unclosed quote runs to the end
+fn protect_blockquotes(
+    html: &str,
+    options: &HtmlOptions,
+    blocks: &mut Vec<String>,
+) -> String {
+    let is_close = |tag: &regex::Match| tag.as_str().starts_with("</");
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+
+    while let Some(open) = BLOCKQUOTE_TAG_REGEX.find_iter(rest).find(|tag| !is_close(tag)) {
+        text.push_str(&rest[..open.start()]);
+        let inner = &rest[open.end()..];
+
+        // Find the matching close tag, skipping over nested quotes
+        let mut depth = 1;
+        let mut end = (inner.len(), inner.len());
+        for tag in BLOCKQUOTE_TAG_REGEX.find_iter(inner) {
+            depth += if is_close(&tag) { -1 } else { 1 };
+            if depth == 0 {
+                end = (tag.start(), tag.end());
+                break;
+            }
+        }
+
+        let quote = clean_html_with(&inner[..end.0], options)
+            .lines()
+            .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {}", line) })
+            .collect::<Vec<_>>()
+            .join("\n");
+        blocks.push(quote);
+        text.push_str(&format!("{}{}{}", PROTECTED_START, blocks.len() - 1, PROTECTED_END));
+        rest = &inner[end.1..];
+    }
+
+    // Stray close tags are dropped along with the other leftover tags
+    text.push_str(rest);
     text
 }
 
+/// Replace each <pre> block with a placeholder, pushing the finished fenced
+/// block onto `blocks`
+fn protect_code_blocks(html: &str, blocks: &mut Vec<String>) -> String {
+    PRE_REGEX
+        .replace_all(html, |caps: &regex::Captures| {
+            let code = BR_REGEX.replace_all(&caps[1], "\n");
+            let code = TAG_REGEX.replace_all(&code, "");
+            let code = decode_html_entities(&code);
+            blocks.push(format!("```\n{}\n```", code.trim_matches('\n')));
+            format!("{}{}{}", PROTECTED_START, blocks.len() - 1, PROTECTED_END)
+        })
+        .to_string()
+}
+
+/// Put set-aside text back in place of the placeholders matched by `placeholder_regex`
+fn restore_placeholders(text: &str, placeholder_regex: &Regex, items: &[String]) -> String {
+    if items.is_empty() {
+        return text.to_string();
+    }
+    placeholder_regex
+        .replace_all(text, |caps: &regex::Captures| {
+            let index: usize = caps[1].parse().unwrap_or(usize::MAX);
+            items.get(index).cloned().unwrap_or_default()
+        })
+        .to_string()
+}
+
+/// Turn <h1>…<h6> into Markdown headings on their own line
+fn convert_headings(html: &str) -> String {
+    HEADING_REGEX
+        .replace_all(html, |caps: &regex::Captures| {
+            let level: usize = caps[1].parse().unwrap_or(1);
+            format!("\n\n{} {}\n\n", "#".repeat(level), list_item_text(&caps[2]))
+        })
+        .to_string()
+}
+
+/// Turn <ul>/<ol> items into Markdown list lines, one item per line
+fn convert_lists(html: &str) -> String {
+    // Number the items of each ordered list (nested lists aren't supported)
+    let text = OL_REGEX.replace_all(html, |caps: &regex::Captures| {
+        let mut number = 0;
+        let items = LI_REGEX.replace_all(&caps[1], |item: &regex::Captures| {
+            number += 1;
+            format!("\n{}. {}", number, list_item_text(&item[1]))
+        });
+        format!("\n\n{}\n\n", items)
+    });
+
+    // Lists are set off from surrounding text by a blank line
+    let text = UL_REGEX.replace_all(&text, "\n\n");
+    LI_REGEX
+        .replace_all(&text, |item: &regex::Captures| {
+            format!("\n- {}", list_item_text(&item[1]))
+        })
+        .to_string()
+}
+
+/// Item content on a single line, so blank lines never split a list
+fn list_item_text(item: &str) -> String {
+    item.replace(BLOCK_BOUNDARY, " ")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 /// Decode common HTML entities
-fn decode_html_entities(text: &str) -> String {
+pub(crate) fn decode_html_entities(text: &str) -> String {
     let mut result = text.to_string();
 
     // Common entities
@@ -111,34 +361,127 @@ fn decode_html_entities(text: &str) -> String {
     }
 
     // Numeric entities (&#NNN; and &#xHHH;)
-    let decimal_regex = Regex::new(r"&#(\d+);").unwrap();
-    result = decimal_regex
+    result = DECIMAL_ENTITY_REGEX
         .replace_all(&result, |caps: &regex::Captures| {
-            let code: u32 = caps[1].parse().unwrap_or(0);
-            char::from_u32(code).map(|c| c.to_string()).unwrap_or_default()
+            numeric_entity_char(caps[1].parse().ok()).to_string()
         })
         .to_string();
 
-    let hex_regex = Regex::new(r"&#[xX]([0-9a-fA-F]+);").unwrap();
-    result = hex_regex
+    result = HEX_ENTITY_REGEX
         .replace_all(&result, |caps: &regex::Captures| {
-            let code = u32::from_str_radix(&caps[1], 16).unwrap_or(0);
-            char::from_u32(code).map(|c| c.to_string()).unwrap_or_default()
+            numeric_entity_char(u32::from_str_radix(&caps[1], 16).ok()).to_string()
         })
         .to_string();
 
     result
 }
 
+/// The character a numeric entity stands for
+/// As in browsers, NUL, surrogates and anything past U+10FFFF (including
+/// values too long to parse) become U+FFFD instead of vanishing
+fn numeric_entity_char(code: Option<u32>) -> char {
+    code.filter(|&code| code != 0)
+        .and_then(char::from_u32)
+        .unwrap_or(char::REPLACEMENT_CHARACTER)
+}
+
+/// Replace `media:` link targets produced by `clean_html` with real URLs
+///
+/// `](media:file name.mp3)` becomes `](<base_url>/file%20name.mp3)`. Works for
+/// both sound links and images. Filenames may contain one level of parentheses,
+/// as in Anki's "word (1).mp3" duplicates.
+pub fn rewrite_media_urls(markdown: &str, base_url: &str) -> String {
+    let base_url = base_url.trim_end_matches('/');
+    MEDIA_LINK_REGEX
+        .replace_all(markdown, |caps: &regex::Captures| {
+            format!("]({}/{})", base_url, percent_encode(&caps[1]))
+        })
+        .to_string()
+}
+
+/// Percent-encode everything except RFC 3986 unreserved characters
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decode `%XX` escapes; malformed escapes are kept as-is
+pub(crate) fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match hex.filter(|_| bytes[i] == b'%').and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Normalize text for searching, optionally folding case and stripping diacritics
+///
+/// Diacritics are removed by decomposing (NFD) and dropping combining marks,
+/// so "Café" and "cafe" fold to the same string.
+pub fn fold_for_search(text: &str, case_insensitive: bool, ignore_diacritics: bool) -> String {
+    let text: String = if ignore_diacritics {
+        text.nfd().filter(|c| !is_combining_mark(*c)).collect()
+    } else {
+        text.nfc().collect()
+    };
+
+    if case_insensitive {
+        text.to_lowercase()
+    } else {
+        text
+    }
+}
+
 /// Process all fields in a card, cleaning HTML
 pub fn process_card_fields(fields: &[String]) -> Vec<String> {
-    fields.iter().map(|f| clean_html(f)).collect()
+    process_card_fields_with(fields, &HtmlOptions::default())
+}
+
+/// `process_card_fields` with extra options, see `clean_html_with`
+pub fn process_card_fields_with(fields: &[String], options: &HtmlOptions) -> Vec<String> {
+    fields.iter().map(|f| clean_html_with(f, options)).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("my%20image%2Epng"), "my image.png");
+        assert_eq!(percent_decode("caf%C3%A9"), "café");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+    }
+
+    #[test]
+    fn test_fold_for_search() {
+        assert_eq!(fold_for_search("Café ÉTÉ", true, true), "cafe ete");
+        assert_eq!(fold_for_search("Café", false, true), "Cafe");
+        assert_eq!(fold_for_search("Café", true, false), "café");
+        // Decomposed input is composed so it matches precomposed queries
+        assert_eq!(fold_for_search("Cafe\u{301}", false, false), "Café");
+    }
+
     #[test]
     fn test_sound_conversion() {
         let html = "Word [sound:pronunciation.mp3]";
@@ -168,6 +511,38 @@ mod tests {
         assert!(result.contains("Paragraph 2"));
     }
 
+    #[test]
+    fn test_block_boundaries() {
+        assert_eq!(clean_html("<div>a<br>b</div><div>c</div>"), "a\nb\nc");
+        assert_eq!(clean_html("<div><div>x</div></div><div>y</div>"), "x\ny");
+        assert_eq!(clean_html("a<div></div>b"), "a\nb");
+        assert_eq!(clean_html("<div>\n  a\n</div>\n<p>b</p>"), "a\nb");
+        // Anki's empty line is a block holding only a <br>
+        assert_eq!(clean_html("<div>a</div><div><br></div><div>b</div>"), "a\n\nb");
+    }
+
+    #[test]
+    fn test_media_filename_entities() {
+        let html = "[sound:a&amp;b.mp3] <img src=\"x&amp;amp;y.png\">";
+        let result = clean_html(html);
+        // Decoded exactly once
+        assert_eq!(
+            result,
+            "[🔊 a&b.mp3](media:a&b.mp3) ![x&amp;y.png](media:x&amp;y.png)"
+        );
+    }
+
+    #[test]
+    fn test_rewrite_media_urls() {
+        let markdown = clean_html("[sound:word (1).mp3] <img src=\"café#2.png\">");
+        let result = rewrite_media_urls(&markdown, "saegim://media/");
+        assert_eq!(
+            result,
+            "[🔊 word (1).mp3](saegim://media/word%20%281%29.mp3) \
+             ![café#2.png](saegim://media/caf%C3%A9%232.png)"
+        );
+    }
+
     #[test]
     fn test_html_entities() {
         let html = "Tom &amp; Jerry &lt;3";
@@ -193,10 +568,93 @@ mod tests {
         assert!(result.contains("![picture.png](media:picture.png)"));
     }
 
+    #[test]
+    fn test_code_list_and_prose() {
+        let html = "<p>Intro</p>\
+            <pre>fn main() {<br><br><br>    x &lt; 1\n}</pre>\
+            <ul><li>one</li><li><div>two</div></li></ul>\
+            <ol><li>first</li><li>second</li></ol>\
+            <div>Outro</div><div><br></div><div><br></div><div>End</div>";
+        let result = clean_html(html);
+        assert_eq!(
+            result,
+            "Intro\n\n```\nfn main() {\n\n\n    x < 1\n}\n```\n\n- one\n- two\n\n1. first\n2. second\n\nOutro\n\nEnd"
+        );
+    }
+
+    #[test]
+    fn test_template_markers() {
+        let html = "{{#Tags}}Tagged{{/Tags}} {{^Extra}}none{{/Extra}}{{type:Answer}} {{c1::kept}}";
+        let result = clean_html(html);
+        assert_eq!(result, "Tagged none {{c1::kept}}");
+    }
+
     #[test]
     fn test_numeric_entities() {
         let html = "&#65;&#66;&#67; and &#x41;&#x42;&#x43;";
         let result = clean_html(html);
         assert_eq!(result, "ABC and ABC");
     }
+
+    #[test]
+    fn test_blockquote() {
+        let html = "Example:<blockquote>line <b>one</b><br>line two</blockquote>after";
+        assert_eq!(clean_html(html), "Example:\n\n> line one\n> line two\n\nafter");
+
+        let html = "<BLOCKQUOTE>outer<blockquote>inner</blockquote>[sound:a.mp3]</blockquote>";
+        assert_eq!(
+            clean_html(html),
+            "> outer\n>\n> > inner\n>\n> [🔊 a.mp3](media:a.mp3)"
+        );
+    }
+
+    #[test]
+    fn test_headings() {
+        assert_eq!(clean_html("<h1>Verbs</h1>body"), "# Verbs\n\nbody");
+        assert_eq!(
+            clean_html(r#"intro<h2 class="title">Past <b>tense</b></h2><div>text</div>"#),
+            "intro\n\n## Past tense\n\ntext"
+        );
+        assert_eq!(clean_html("<H3>Multi<br>line</H3>"), "### Multi line");
+    }
+
+    #[test]
+    fn test_horizontal_rule() {
+        assert_eq!(clean_html("a<hr>b"), "a\n\n---\n\nb");
+        assert_eq!(clean_html("a<div><hr/></div><hr id=answer>b"), "a\n\n---\n\n---\n\nb");
+    }
+
+    #[test]
+    fn test_collapse_spaces() {
+        let html = "a&nbsp;&nbsp; &nbsp;b  c\u{A0}\u{A0}d<pre>    indented  code</pre>";
+        let options = HtmlOptions { collapse_spaces: true, ..Default::default() };
+        assert_eq!(clean_html_with(html, &options), "a b c d\n\n```\n    indented  code\n```");
+        // Off by default
+        assert_eq!(clean_html("a&nbsp;&nbsp;b"), "a  b");
+    }
+
+    #[test]
+    fn test_invalid_numeric_entities() {
+        assert_eq!(decode_html_entities("a&#x110000;b"), "a\u{FFFD}b");
+        assert_eq!(decode_html_entities("a&#0;b"), "a\u{FFFD}b");
+        assert_eq!(decode_html_entities("&#xD800;&#99999999999;"), "\u{FFFD}\u{FFFD}");
+        // Leading zeros and uppercase hex still decode
+        assert_eq!(decode_html_entities("&#X00041;&#0065;&#x1F600;"), "AA\u{1F600}");
+    }
+
+    #[test]
+    fn test_fixed_patterns_compile() {
+        // Forcing each static surfaces a bad pattern here instead of mid-parse
+        let statics: [&LazyLock<Regex>; 24] = [
+            &SOUND_REGEX, &IMG_SRC_REGEX, &IMG_TAG_REGEX, &BLOCK_REGEX, &BOUNDARY_RUN_REGEX,
+            &BR_REGEX, &SPAN_REGEX, &FORMAT_REGEX, &TAG_REGEX, &MULTI_NEWLINE_REGEX,
+            &TEMPLATE_MARKER_REGEX, &PRE_REGEX, &PROTECTED_PLACEHOLDER_REGEX,
+            &LINK_PLACEHOLDER_REGEX, &LI_REGEX, &OL_REGEX, &UL_REGEX, &DECIMAL_ENTITY_REGEX,
+            &HEX_ENTITY_REGEX, &MEDIA_LINK_REGEX, &BLOCKQUOTE_TAG_REGEX,
+            &HEADING_REGEX, &HR_REGEX, &SPACE_RUN_REGEX,
+        ];
+        for regex in statics {
+            LazyLock::force(regex);
+        }
+    }
 }
@@ -10,17 +10,30 @@
 //! Designed for integration with Swift via UniFFI bindings.
 
 pub mod archive;
+#[cfg(feature = "cache")]
+pub mod cache;
 pub mod database;
 pub mod error;
+pub mod export;
 pub mod html;
 pub mod media;
 pub mod models;
+pub mod schedule;
+pub mod template;
 
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
-use archive::AnkiArchive;
-use database::AnkiDatabase;
-use error::AnkiError;
-use models::{AnkiCollection, AnkiDeck, AnkiProgress, AnkiProgressCallback};
+use archive::{AnkiArchive, AnkiFormat};
+use database::{AnkiDatabase, MAX_SCHEMA_VERSION};
+use error::{check_deadline, AnkiError};
+use models::{
+    AnkiCard, AnkiCollection, AnkiDeck, AnkiProgress, AnkiProgressCallback, CollectionMeta,
+    CollectionSummary, DeckSortMode, ParseEvent, ParseEventSink, ParseOptions, ParseReport,
+};
+
+/// Anki's built-in "Default" deck, present in every collection
+const DEFAULT_DECK_ID: i64 = 1;
 
 // Re-export main types
 pub use error::AnkiError as Error;
@@ -38,28 +51,124 @@ pub use models::{AnkiCard as Card, AnkiCollection as Collection, AnkiDeck as Dec
 /// # Errors
 /// * `AnkiError::FileNotFound` - File does not exist
 /// * `AnkiError::InvalidArchive` - Not a valid Anki archive
+/// * `AnkiError::EncryptedArchive` - Archive is password-protected and no (or a wrong)
+///   password was given in `ParseOptions.password`
+/// * `AnkiError::UnsupportedCompression` - Zip entries use an unsupported method
+/// * `AnkiError::InvalidDatabaseHeader` - Collection is not an SQLite database
 /// * `AnkiError::DatabaseError` - Error reading SQLite database
 /// * `AnkiError::DecompressionError` - Error decompressing zstd data
+/// * `AnkiError::Timeout` - Parsing ran past `ParseOptions.timeout_ms`
+/// * `AnkiError::InvalidPattern` - A `ParseOptions.sound_patterns` entry is not a valid
+///   regex with a capture group
+/// * `AnkiError::UnsupportedSchemaVersion` - Collection schema is newer than this parser
+///   supports and `ParseOptions.force` is off
+///
+/// With the `cache` feature, a file parsed recently (by content, see
+/// `collection_fingerprint`) is returned from the cache, reporting only `Complete`.
 #[uniffi::export]
 pub fn parse_anki_file(
     file_path: String,
     progress_callback: Box<dyn AnkiProgressCallback>,
 ) -> Result<AnkiCollection, AnkiError> {
+    #[cfg(feature = "cache")]
+    {
+        let fingerprint = collection_fingerprint(file_path.clone())?;
+        if let Some(collection) = cache::get(&fingerprint) {
+            progress_callback.on_progress(AnkiProgress::Complete);
+            return Ok(collection);
+        }
+        let collection =
+            parse_anki_file_with_options(file_path, ParseOptions::default(), progress_callback)?;
+        cache::insert(fingerprint, &collection);
+        Ok(collection)
+    }
+
+    #[cfg(not(feature = "cache"))]
+    parse_anki_file_with_options(file_path, ParseOptions::default(), progress_callback)
+}
+
+/// Drop every collection cached by `parse_anki_file`
+#[cfg(feature = "cache")]
+#[uniffi::export]
+pub fn clear_parse_cache() {
+    cache::clear();
+}
+
+/// Set how many collections `parse_anki_file` keeps cached (default 4)
+///
+/// Each cached collection holds all of its cards and media in memory.
+/// Zero disables the cache.
+#[cfg(feature = "cache")]
+#[uniffi::export]
+pub fn set_parse_cache_capacity(capacity: u32) {
+    cache::set_capacity(capacity as usize);
+}
+
+/// Parse an Anki .apkg or .colpkg file with explicit options
+///
+/// `parse_anki_file` is this with `ParseOptions::default()`.
+/// Errors are the same as for `parse_anki_file`.
+#[uniffi::export]
+pub fn parse_anki_file_with_options(
+    file_path: String,
+    options: ParseOptions,
+    progress_callback: Box<dyn AnkiProgressCallback>,
+) -> Result<AnkiCollection, AnkiError> {
+    let deadline = options.deadline();
+    // Fail on bad user patterns before doing any work
+    html::compile_sound_patterns(&options.sound_patterns)?;
+
+    // Tally events into the report, still forwarding them to the caller's sink
+    let reporter = Arc::new(ReportingSink {
+        report: Mutex::new(ParseReport::default()),
+        inner: options.event_sink.clone(),
+    });
+    let options = ParseOptions {
+        event_sink: Some(reporter.clone()),
+        ..options
+    };
+
     // Phase 1: Extract archive
     progress_callback.on_progress(AnkiProgress::Extracting);
-    let mut archive = AnkiArchive::open(&file_path)?;
+    let mut archive = match &options.password {
+        Some(password) => AnkiArchive::open_with_password(&file_path, password)?,
+        None => AnkiArchive::open(&file_path)?,
+    };
+    archive.set_max_database_bytes(options.max_database_bytes);
 
     // Phase 2: Parse database
+    check_deadline(deadline)?;
+    let db = open_database(&mut archive, |phase| progress_callback.on_progress(phase))?;
+
+    // Newer schemas may have changed in ways that make the queries below fail
+    // confusingly, so refuse them up front unless forced
+    let schema_version = db.schema_version()?;
+    if schema_version > MAX_SCHEMA_VERSION {
+        if !options.force {
+            return Err(AnkiError::UnsupportedSchemaVersion {
+                found: schema_version,
+                supported_max: MAX_SCHEMA_VERSION,
+            });
+        }
+        log::warn!("Parsing unsupported schema version {} anyway", schema_version);
+    }
     progress_callback.on_progress(AnkiProgress::ReadingDecks);
-    let db_data = archive.extract_database()?;
-    let db = AnkiDatabase::open_from_bytes(&db_data)?;
 
-    // Parse decks
-    let mut decks = db.parse_decks()?;
+    // Parse collection metadata and decks
+    let meta = db.parse_collection_meta()?;
+    let mut decks = db.parse_decks_with_separator(
+        DeckSortMode::default(),
+        options.deck_separator.as_deref(),
+    )?;
+    // Note types are optional; cards still parse without them
+    let note_types = db.note_types();
+    let tags = db.parse_all_tags().unwrap_or_default();
+    let note_count = db.note_count()? as u32;
 
     // Phase 3: Parse cards
+    check_deadline(deadline)?;
     progress_callback.on_progress(AnkiProgress::ReadingCards);
-    let cards_by_deck = db.parse_cards(|_current, _total| {
+    let cards_by_deck = db.parse_cards_until(&options, deadline, |_current, _total| {
         // Could add more granular progress here
     })?;
 
@@ -69,21 +178,153 @@ pub fn parse_anki_file(
     // Check for missing decks and create them from card deck IDs
     for deck_id in cards_by_deck.keys() {
         if !known_deck_ids.contains(deck_id) {
-            // Create a placeholder deck for orphaned cards
-            decks.push(AnkiDeck::from_name(*deck_id, format!("Deck {}", deck_id)));
+            // Create a placeholder deck for orphaned cards, ordered after the real ones
+            let mut deck = AnkiDeck::from_name(*deck_id, format!("Deck {}", deck_id));
+            deck.order = decks.len() as u32;
+            decks.push(deck);
+            options.emit(ParseEvent::OrphanDeckCreated { id: *deck_id });
+        }
+    }
+
+    // Drop the built-in Default deck unless it, or a deck nested under it, holds cards
+    if let Some(default_deck) = decks.iter().find(|d| d.id == DEFAULT_DECK_ID) {
+        let prefix = format!("{}::", default_deck.name);
+        let in_use = decks.iter().any(|d| {
+            (d.id == DEFAULT_DECK_ID || d.name.starts_with(&prefix))
+                && cards_by_deck.contains_key(&d.id)
+        });
+        if !in_use {
+            decks.retain(|d| d.id != DEFAULT_DECK_ID);
         }
     }
 
     // Phase 4: Process media
+    check_deadline(deadline)?;
     progress_callback.on_progress(AnkiProgress::ProcessingMedia);
-    let media = media::process_media(&mut archive, |_current, _total| {
-        // Could add more granular progress here
-    })?;
+    let media = media::process_media(
+        &mut archive,
+        &options,
+        deadline,
+        |_current, _total| {
+            // Could add more granular progress here
+        },
+    )?;
 
     // Phase 5: Complete
     progress_callback.on_progress(AnkiProgress::Complete);
 
-    Ok(AnkiCollection::new(decks, cards_by_deck, media))
+    let mut report = reporter.report.lock().unwrap().clone();
+    report.format = Some(archive.format());
+    report.deck_count = decks.len() as u32;
+    report.card_count = cards_by_deck.values().map(Vec::len).sum::<usize>() as u32;
+    report.media_counts = media.count_by_type();
+
+    let mut collection = AnkiCollection::new(decks, cards_by_deck, media, meta, note_types);
+    collection.tags = tags;
+    collection.note_count = note_count;
+    collection.report = report;
+    Ok(collection)
+}
+
+/// Event sink that tallies events into a `ParseReport` before forwarding them
+struct ReportingSink {
+    report: Mutex<ParseReport>,
+    inner: Option<Arc<dyn ParseEventSink>>,
+}
+
+impl ParseEventSink for ReportingSink {
+    fn on_event(&self, event: ParseEvent) {
+        self.report.lock().unwrap().record(&event);
+        if let Some(inner) = &self.inner {
+            inner.on_event(event);
+        }
+    }
+}
+
+/// Summarize an Anki .apkg or .colpkg file without fully parsing it
+///
+/// Counts decks, cards (overall and per deck) and media, and sums the stored
+/// size of media entries.
+/// Media is not decompressed and no card objects are built, so this is fast
+/// enough to run before asking the user to confirm a large import.
+#[uniffi::export]
+pub fn inspect_anki_file(file_path: String) -> Result<CollectionSummary, AnkiError> {
+    let mut archive = AnkiArchive::open(&file_path)?;
+    let db = open_database(&mut archive, |_| {})?;
+
+    let deck_count = db.parse_decks(DeckSortMode::default())?.len() as u32;
+    let card_counts_by_deck: HashMap<String, u32> = db
+        .card_ids_by_deck()?
+        .into_iter()
+        .map(|(deck_id, ids)| (deck_id.to_string(), ids.len() as u32))
+        .collect();
+    let card_count = card_counts_by_deck.values().sum();
+
+    let mapping = media::extract_media_mapping(&mut archive, media::DEFAULT_MAX_MEDIA_BYTES)?;
+    let media_bytes = archive.media_compressed_size(mapping.keys());
+
+    Ok(CollectionSummary {
+        deck_count,
+        card_count,
+        card_counts_by_deck,
+        media_count: mapping.len() as u32,
+        media_bytes,
+    })
+}
+
+/// Fingerprint an Anki .apkg or .colpkg file without parsing it
+///
+/// Returns a 16-digit hex xxh3 hash of the raw archive bytes, so it changes
+/// whenever the file changes at all (including re-zipping identical content)
+/// and never otherwise. Meant for caching parse results, not for security.
+#[uniffi::export]
+pub fn collection_fingerprint(path: String) -> Result<String, AnkiError> {
+    use std::io::Read;
+
+    let path = std::path::Path::new(&path);
+    if !path.exists() {
+        return Err(AnkiError::FileNotFound(path.display().to_string()));
+    }
+
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:016x}", hasher.digest()))
+}
+
+/// Extract the collection database from an archive and open it
+fn open_database(
+    archive: &mut AnkiArchive,
+    mut on_progress: impl FnMut(AnkiProgress),
+) -> Result<AnkiDatabase, AnkiError> {
+    if archive.format() == AnkiFormat::Compressed {
+        on_progress(AnkiProgress::DecompressingDatabase);
+        let db_data = archive.extract_database()?;
+        on_progress(AnkiProgress::OpeningDatabase);
+        AnkiDatabase::open_from_bytes(&db_data)
+    } else {
+        // Uncompressed databases stream straight to disk, skipping an in-memory copy
+        match archive.extract_database_to_file(&std::env::temp_dir()) {
+            Ok(db_path) => {
+                on_progress(AnkiProgress::OpeningDatabase);
+                AnkiDatabase::open_owned_path(db_path)
+            }
+            // An unwritable temp dir; open_from_bytes falls back to memory
+            Err(AnkiError::IoError(_) | AnkiError::FileNotFound(_)) => {
+                let db_data = archive.extract_database()?;
+                on_progress(AnkiProgress::OpeningDatabase);
+                AnkiDatabase::open_from_bytes(&db_data)
+            }
+            Err(e) => Err(e),
+        }
+    }
 }
 
 /// Clean HTML content to Markdown
@@ -99,14 +340,100 @@ pub fn clean_html_to_markdown(html: String) -> String {
     html::clean_html(&html)
 }
 
+/// Clean many fields in one call, in input order
+///
+/// Same output as `clean_html_to_markdown` per field, but crosses the FFI
+/// boundary once for the whole batch.
+#[uniffi::export]
+pub fn clean_html_batch(fields: Vec<String>) -> Vec<String> {
+    html::process_card_fields(&fields)
+}
+
+/// Replace `media:` placeholders in cleaned text with URLs under `base_url`
+///
+/// Filenames are percent-encoded, so any filename is safe to pass through.
+#[uniffi::export]
+pub fn rewrite_media_urls(markdown: String, base_url: String) -> String {
+    html::rewrite_media_urls(&markdown, &base_url)
+}
+
+/// Get the original HTML of a card field
+///
+/// Returns the untouched field even when the card was parsed with
+/// `ParseOptions.clean_html`, for faithful rendering in a web view.
+/// None if `index` is out of range.
+#[uniffi::export]
+pub fn card_field_html(card: AnkiCard, index: u32) -> Option<String> {
+    let fields = if card.raw_fields.is_empty() {
+        card.fields
+    } else {
+        card.raw_fields
+    };
+    fields.into_iter().nth(index as usize)
+}
+
+/// Check whether any of a card's fields contains `query`
+///
+/// Fields are searched as cleaned text (HTML is stripped first if the card
+/// holds raw fields). Both sides can have their case folded and diacritics
+/// stripped, so "cafe" matches "Café". An empty query matches every card.
+#[uniffi::export]
+pub fn card_matches(
+    card: AnkiCard,
+    query: String,
+    case_insensitive: bool,
+    ignore_diacritics: bool,
+) -> bool {
+    let query = html::fold_for_search(&query, case_insensitive, ignore_diacritics);
+    if query.is_empty() {
+        return true;
+    }
+
+    card.fields.iter().any(|field| {
+        let text = if card.raw_fields.is_empty() {
+            html::clean_html(field)
+        } else {
+            field.clone()
+        };
+        html::fold_for_search(&text, case_insensitive, ignore_diacritics).contains(&query)
+    })
+}
+
+/// Export cards as tab-separated text for other tools
+///
+/// See `export::cards_tsv`: a header row, then deck name, tags and each cleaned
+/// field per card. `deck_id` limits the export to one deck; `None` exports all.
+#[uniffi::export]
+pub fn export_cards_tsv(collection: AnkiCollection, deck_id: Option<i64>) -> String {
+    export::cards_tsv(&collection, deck_id)
+}
+
+/// Write the collection to an `.apkg` file at `path`
+///
+/// See `export::write_apkg`: decks, note types, notes, cards and media are
+/// written; review history isn't, so review cards come back due immediately.
+#[uniffi::export]
+pub fn write_apkg(collection: AnkiCollection, path: String) -> Result<(), AnkiError> {
+    export::write_apkg(&collection, path)
+}
+
+/// Days since a card was last reviewed, for FSRS's `days_elapsed`
+///
+/// `meta` is the card's collection's `AnkiCollection.meta`; day boundaries follow
+/// its scheduler version and rollover hour. See `schedule::days_elapsed_for_card`.
+#[uniffi::export]
+pub fn days_elapsed_for_card(card: AnkiCard, meta: CollectionMeta, now_epoch_ms: i64) -> u32 {
+    schedule::days_elapsed_for_card(&card, &meta, now_epoch_ms)
+}
+
 // Setup UniFFI scaffolding using proc-macros
 uniffi::setup_scaffolding!();
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::database::tests::{insert_note_sql, COL_ROW, SCHEMA};
     use std::sync::atomic::{AtomicU32, Ordering};
-    use std::sync::Arc;
 
     struct TestProgressCallback {
         progress_count: AtomicU32,
@@ -118,15 +445,384 @@ mod tests {
         }
     }
 
+    /// Records every phase, for checking their order
+    struct RecordingProgressCallback {
+        phases: Arc<Mutex<Vec<AnkiProgress>>>,
+    }
+
+    impl AnkiProgressCallback for RecordingProgressCallback {
+        fn on_progress(&self, progress: AnkiProgress) {
+            self.phases.lock().unwrap().push(progress);
+        }
+    }
+
+    /// Write an .apkg containing a legacy collection built from SQL
+    pub(crate) fn build_apkg(sql: &str) -> tempfile::NamedTempFile {
+        use std::io::Write;
+
+        let db_file = tempfile::NamedTempFile::new().unwrap();
+        rusqlite::Connection::open(db_file.path())
+            .unwrap()
+            .execute_batch(sql)
+            .unwrap();
+        let db_data = std::fs::read(db_file.path()).unwrap();
+
+        let apkg = tempfile::NamedTempFile::new().unwrap();
+        let mut zip = zip::ZipWriter::new(apkg.reopen().unwrap());
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("collection.anki2", options).unwrap();
+        zip.write_all(&db_data).unwrap();
+        zip.start_file("media", options).unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.finish().unwrap();
+        apkg
+    }
+
+    fn parse_fixture(sql: &str) -> AnkiCollection {
+        let apkg = build_apkg(sql);
+        let callback = Box::new(TestProgressCallback {
+            progress_count: AtomicU32::new(0),
+        });
+        parse_anki_file(apkg.path().display().to_string(), callback).unwrap()
+    }
+
+    #[test]
+    fn test_parse_with_options() {
+        let apkg = build_apkg(&format!(
+            "{SCHEMA}{COL_ROW}{}",
+            insert_note_sql(10, 20, 1, "<i>front</i>\x1fback")
+        ));
+        let callback = Box::new(TestProgressCallback {
+            progress_count: AtomicU32::new(0),
+        });
+        let options = ParseOptions { clean_html: true, ..Default::default() };
+        let collection =
+            parse_anki_file_with_options(apkg.path().display().to_string(), options, callback)
+                .unwrap();
+
+        assert_eq!(collection.cards_by_deck["1"][0].fields[0], "front");
+    }
+
+    #[derive(Default)]
+    struct CollectingSink {
+        events: std::sync::Mutex<Vec<ParseEvent>>,
+    }
+
+    impl models::ParseEventSink for CollectingSink {
+        fn on_event(&self, event: ParseEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[test]
+    fn test_progress_phases() {
+        let sql = format!("{SCHEMA}{COL_ROW}{}", insert_note_sql(10, 20, 1, "front"));
+        let parse = |path: &std::path::Path| {
+            let phases = Arc::new(Mutex::new(Vec::new()));
+            let callback = Box::new(RecordingProgressCallback { phases: phases.clone() });
+            parse_anki_file(path.display().to_string(), callback).unwrap();
+            let phases = phases.lock().unwrap().clone();
+            phases
+        };
+
+        let legacy = build_apkg(&sql);
+        assert_eq!(
+            parse(legacy.path()),
+            vec![
+                AnkiProgress::Extracting,
+                AnkiProgress::OpeningDatabase,
+                AnkiProgress::ReadingDecks,
+                AnkiProgress::ReadingCards,
+                AnkiProgress::ProcessingMedia,
+                AnkiProgress::Complete,
+            ]
+        );
+
+        // Repackage the same database as a zstd-compressed collection.anki21b
+        let mut archive = AnkiArchive::open(legacy.path()).unwrap();
+        let compressed = zstd::encode_all(&archive.extract_database().unwrap()[..], 0).unwrap();
+        let compressed_apkg = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            compressed_apkg.path(),
+            crate::archive::tests::zip_bytes(&[("collection.anki21b", &compressed)]),
+        )
+        .unwrap();
+        assert_eq!(
+            parse(compressed_apkg.path())[..4],
+            [
+                AnkiProgress::Extracting,
+                AnkiProgress::DecompressingDatabase,
+                AnkiProgress::OpeningDatabase,
+                AnkiProgress::ReadingDecks,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_schema_version() {
+        let apkg = build_apkg(&format!(
+            "{SCHEMA}{COL_ROW}{}UPDATE col SET ver = 99;",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        let parse = |force| {
+            let callback = Box::new(TestProgressCallback {
+                progress_count: AtomicU32::new(0),
+            });
+            let options = ParseOptions { force, ..Default::default() };
+            parse_anki_file_with_options(apkg.path().display().to_string(), options, callback)
+        };
+
+        assert!(matches!(
+            parse(false),
+            Err(AnkiError::UnsupportedSchemaVersion {
+                found: 99,
+                supported_max: MAX_SCHEMA_VERSION
+            })
+        ));
+        assert_eq!(parse(true).unwrap().total_card_count(), 1);
+    }
+
+    #[cfg(feature = "cache")]
+    #[test]
+    fn test_parse_cache() {
+        let _guard = cache::tests::CACHE_TEST_LOCK.lock().unwrap();
+        // Unique content, so no other test's parse shares the fingerprint
+        let apkg = build_apkg(&format!(
+            "{SCHEMA}{COL_ROW}{}",
+            insert_note_sql(10, 20, 1, "cached")
+        ));
+        let parse = || {
+            let phases = Arc::new(Mutex::new(Vec::new()));
+            let callback = Box::new(RecordingProgressCallback { phases: phases.clone() });
+            let collection = parse_anki_file(apkg.path().display().to_string(), callback).unwrap();
+            let phases = phases.lock().unwrap().clone();
+            (collection, phases)
+        };
+
+        let (first, phases) = parse();
+        assert!(phases.len() > 1);
+        let (second, phases) = parse();
+        assert_eq!(phases, vec![AnkiProgress::Complete]);
+        assert_eq!(second.cards_by_deck["1"][0].fields, first.cards_by_deck["1"][0].fields);
+        assert!(Arc::ptr_eq(&first.media, &second.media));
+
+        clear_parse_cache();
+        assert!(parse().1.len() > 1);
+    }
+
+    #[test]
+    fn test_parse_events() {
+        // Card 11 sits in a deck that doesn't exist and has no note
+        let apkg = build_apkg(&format!(
+            "{SCHEMA}{COL_ROW}{}
+             INSERT INTO cards VALUES (11, 999, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        let callback = Box::new(TestProgressCallback {
+            progress_count: AtomicU32::new(0),
+        });
+        let sink = std::sync::Arc::new(CollectingSink::default());
+        let options = ParseOptions { event_sink: Some(sink.clone()), ..Default::default() };
+        parse_anki_file_with_options(apkg.path().display().to_string(), options, callback)
+            .unwrap();
+
+        let events = sink.events.lock().unwrap();
+        assert!(events.contains(&ParseEvent::NotesMissing { count: 1 }));
+        assert!(events.contains(&ParseEvent::OrphanDeckCreated { id: 7 }));
+    }
+
+    #[test]
+    fn test_parse_report() {
+        let collection = parse_fixture(&format!(
+            "{SCHEMA}{COL_ROW}{}
+             INSERT INTO cards VALUES (11, 999, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, '');",
+            insert_note_sql(10, 20, 1, "front")
+        ));
+
+        assert_eq!(collection.note_count, 1);
+
+        let report = &collection.report;
+        assert_eq!(report.format, Some(AnkiFormat::Legacy));
+        assert_eq!(report.deck_count, 2);
+        assert_eq!(report.card_count, 2);
+        assert_eq!(report.orphan_decks_created, 1);
+        assert_eq!(report.missing_notes, 1);
+        assert_eq!(report.failed_media, 0);
+        assert!(report.media_counts.is_empty());
+    }
+
+    #[test]
+    fn test_parse_timeout() {
+        let apkg = build_apkg(&format!("{SCHEMA}{COL_ROW}{}", insert_note_sql(10, 20, 1, "front")));
+        let callback = Box::new(TestProgressCallback {
+            progress_count: AtomicU32::new(0),
+        });
+        let options = ParseOptions { timeout_ms: Some(0), ..Default::default() };
+        let result =
+            parse_anki_file_with_options(apkg.path().display().to_string(), options, callback);
+
+        assert!(matches!(result, Err(AnkiError::Timeout)));
+    }
+
+    #[test]
+    fn test_empty_default_deck_skipped() {
+        let collection = parse_fixture(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{{}}',
+                '{{"1": {{"name": "Default"}}, "2": {{"name": "Korean"}}}}', '{{}}', '{{}}');
+            {}"#,
+            insert_note_sql(10, 20, 2, "front")
+        ));
+        let ids: Vec<i64> = collection.decks.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn test_non_empty_default_deck_kept() {
+        let collection = parse_fixture(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{{}}',
+                '{{"1": {{"name": "Default"}}, "2": {{"name": "Korean"}}}}', '{{}}', '{{}}');
+            {}"#,
+            insert_note_sql(10, 20, 1, "front")
+        ));
+        assert!(collection.decks.iter().any(|d| d.id == DEFAULT_DECK_ID));
+    }
+
+    #[test]
+    fn test_default_deck_kept_for_subdeck_cards() {
+        let collection = parse_fixture(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{{}}',
+                '{{"1": {{"name": "Default"}}, "2": {{"name": "Default::Verbs"}}}}',
+                '{{}}', '{{}}');
+            {}"#,
+            insert_note_sql(10, 20, 2, "front")
+        ));
+        let ids: Vec<i64> = collection.decks.iter().map(|d| d.id).collect();
+        assert_eq!(ids, vec![DEFAULT_DECK_ID, 2]);
+        assert_eq!(collection.root_decks.len(), 1);
+    }
+
+    #[test]
+    fn test_inspect_anki_file() {
+        let apkg = build_apkg(&format!(
+            r#"{SCHEMA}
+            INSERT INTO col VALUES (1, 0, 0, 0, 11, 0, 0, 0, '{{}}', '{{}}',
+                '{{"1": {{"name": "Default"}}, "2": {{"name": "Korean"}}, "3": {{"name": "Korean::Verbs"}}}}',
+                '{{}}', '{{}}');
+            {}{}"#,
+            insert_note_sql(10, 20, 2, "a"),
+            insert_note_sql(11, 21, 3, "b")
+        ));
+        let summary = inspect_anki_file(apkg.path().display().to_string()).unwrap();
+        assert_eq!(summary.deck_count, 3);
+        assert_eq!(summary.card_count, 2);
+        assert_eq!(summary.card_counts_by_deck["2"], 1);
+        assert_eq!(summary.card_counts_by_deck["3"], 1);
+        assert_eq!(summary.media_count, 0);
+        assert_eq!(summary.media_bytes, 0);
+    }
+
+    #[test]
+    fn test_collection_fingerprint() {
+        let sql = format!("{SCHEMA}{COL_ROW}{}", insert_note_sql(10, 20, 1, "front"));
+        let fingerprint = |apkg: &tempfile::NamedTempFile| {
+            collection_fingerprint(apkg.path().display().to_string()).unwrap()
+        };
+
+        let apkg = build_apkg(&sql);
+        let first = fingerprint(&apkg);
+        assert_eq!(first.len(), 16);
+        assert_eq!(fingerprint(&apkg), first);
+        assert_ne!(fingerprint(&build_apkg(&format!("{SCHEMA}{COL_ROW}"))), first);
+
+        assert!(matches!(
+            collection_fingerprint("/nonexistent/deck.apkg".to_string()),
+            Err(AnkiError::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_sound_patterns() {
+        let apkg = build_apkg(&format!(
+            "{SCHEMA}{COL_ROW}{}",
+            insert_note_sql(10, 20, 1, "[audio:a.mp3] [tts:b.mp3]\x1f[sound:c.mp3]")
+        ));
+        let parse = |sound_patterns: Vec<String>| {
+            let callback = Box::new(TestProgressCallback {
+                progress_count: AtomicU32::new(0),
+            });
+            let options = ParseOptions { clean_html: true, sound_patterns, ..Default::default() };
+            parse_anki_file_with_options(apkg.path().display().to_string(), options, callback)
+        };
+
+        let collection = parse(vec![r"\[tts:([^\]]+)\]".to_string()]).unwrap();
+        let card = &collection.cards_by_deck["1"][0];
+        assert_eq!(card.media_references, vec!["a.mp3", "b.mp3", "c.mp3"]);
+        assert_eq!(card.fields[0], "[🔊 a.mp3](media:a.mp3) [🔊 b.mp3](media:b.mp3)");
+
+        assert!(matches!(parse(vec!["[unclosed".to_string()]), Err(AnkiError::InvalidPattern(_))));
+        assert!(matches!(parse(vec!["no group".to_string()]), Err(AnkiError::InvalidPattern(_))));
+    }
+
+    #[test]
+    fn test_parse_missing_file() {
+        let callback = Box::new(TestProgressCallback {
+            progress_count: AtomicU32::new(0),
+        });
+        let result = parse_anki_file("/nonexistent/deck.apkg".to_string(), callback);
+        assert!(matches!(result, Err(AnkiError::FileNotFound(_))));
+    }
+
     #[test]
     fn test_clean_html() {
         let result = clean_html_to_markdown("Hello <b>World</b>".to_string());
         assert_eq!(result, "Hello World");
     }
 
+    #[test]
+    fn test_clean_html_batch() {
+        let fields = vec!["<b>a</b>".to_string(), String::new(), "b<br>c".to_string()];
+        assert_eq!(clean_html_batch(fields), vec!["a", "", "b\nc"]);
+    }
+
     #[test]
     fn test_sound_conversion() {
         let result = clean_html_to_markdown("[sound:test.mp3]".to_string());
         assert_eq!(result, "[🔊 test.mp3](media:test.mp3)");
     }
+
+    #[test]
+    fn test_card_field_html() {
+        let raw = AnkiCard {
+            fields: vec!["<b>a</b>".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(card_field_html(raw, 0).as_deref(), Some("<b>a</b>"));
+
+        let cleaned = AnkiCard {
+            fields: vec!["a".to_string()],
+            raw_fields: vec!["<b>a</b>".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(card_field_html(cleaned.clone(), 0).as_deref(), Some("<b>a</b>"));
+        assert_eq!(card_field_html(cleaned, 1), None);
+    }
+
+    #[test]
+    fn test_card_matches() {
+        let card = AnkiCard {
+            fields: vec!["<b>Café</b> au lait".to_string(), "coffee".to_string()],
+            ..Default::default()
+        };
+
+        assert!(card_matches(card.clone(), "Café au".to_string(), false, false));
+        assert!(card_matches(card.clone(), "CAFE".to_string(), true, true));
+        assert!(!card_matches(card.clone(), "cafe".to_string(), true, false));
+        assert!(!card_matches(card.clone(), "CAFÉ".to_string(), false, false));
+        // Markup is not searchable
+        assert!(!card_matches(card.clone(), "<b>".to_string(), false, false));
+        assert!(card_matches(card, String::new(), false, false));
+    }
 }
@@ -1,8 +1,11 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
 use std::sync::Arc;
+use std::time::Instant;
 
 use crate::archive::AnkiArchive;
-use crate::error::AnkiError;
-use crate::models::AnkiMediaStore;
+use crate::error::{check_deadline, AnkiError};
+use crate::models::{AnkiMediaStore, ImageDimensions, ParseEvent, ParseOptions};
 
 /// Known audio file extensions
 const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "m4a", "ogg", "flac", "aac", "opus", "wma"];
@@ -10,9 +13,13 @@ const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav", "m4a", "ogg", "flac", "aac", "
 /// Known image file extensions
 const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "svg", "ico", "tiff"];
 
+/// Known video file extensions
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "webm", "mov", "mkv", "avi", "m4v"];
+
 /// Magic bytes for file format detection
 mod magic {
     pub const ZSTD: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+    pub const GZIP: [u8; 2] = [0x1F, 0x8B];
     pub const JPEG: [u8; 2] = [0xFF, 0xD8];
     pub const PNG: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
     pub const GIF87: [u8; 6] = [0x47, 0x49, 0x46, 0x38, 0x37, 0x61];
@@ -25,13 +32,26 @@ mod magic {
 }
 
 /// Media file type
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
 pub enum MediaType {
     Audio,
     Image,
+    Video,
     Unknown,
 }
 
+impl MediaType {
+    /// Lowercase name used as a key in storage breakdowns
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Audio => "audio",
+            MediaType::Image => "image",
+            MediaType::Video => "video",
+            MediaType::Unknown => "unknown",
+        }
+    }
+}
+
 /// Determine media type from filename extension
 pub fn media_type_from_extension(filename: &str) -> MediaType {
     let ext = filename
@@ -44,6 +64,8 @@ pub fn media_type_from_extension(filename: &str) -> MediaType {
         MediaType::Audio
     } else if IMAGE_EXTENSIONS.contains(&ext.as_str()) {
         MediaType::Image
+    } else if VIDEO_EXTENSIONS.contains(&ext.as_str()) {
+        MediaType::Video
     } else {
         MediaType::Unknown
     }
@@ -54,10 +76,45 @@ pub fn is_zstd_compressed(data: &[u8]) -> bool {
     data.len() >= 4 && data[0..4] == magic::ZSTD
 }
 
-/// Decompress zstd data
-pub fn decompress_zstd(data: &[u8]) -> Result<Vec<u8>, AnkiError> {
-    zstd::decode_all(data)
-        .map_err(|e| AnkiError::DecompressionError(e.to_string()))
+/// Check if data starts with gzip magic bytes
+pub fn is_gzip_compressed(data: &[u8]) -> bool {
+    data.starts_with(&magic::GZIP)
+}
+
+/// Decompress gzip data, failing once the output exceeds `max_output` bytes
+pub fn decompress_gzip_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>, AnkiError> {
+    read_limited(flate2::read::GzDecoder::new(data), max_output)
+}
+
+/// Default cap on the decompressed size of a single media file (256 MB)
+pub const DEFAULT_MAX_MEDIA_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Decompress zstd data, failing once the output exceeds `max_output` bytes
+///
+/// A tiny crafted frame can expand to gigabytes, so the output is always capped.
+pub fn decompress_zstd_limited(data: &[u8], max_output: u64) -> Result<Vec<u8>, AnkiError> {
+    let decoder = zstd::stream::read::Decoder::new(data)
+        .map_err(|e| AnkiError::DecompressionError(e.to_string()))?;
+    read_limited(decoder, max_output)
+}
+
+/// Read a decoder to the end, failing once the output exceeds `max_output` bytes
+fn read_limited<R: Read>(decoder: R, max_output: u64) -> Result<Vec<u8>, AnkiError> {
+    // Read one byte past the cap so overflowing output is detectable
+    let mut output = Vec::new();
+    decoder
+        .take(max_output.saturating_add(1))
+        .read_to_end(&mut output)
+        .map_err(|e| AnkiError::DecompressionError(e.to_string()))?;
+
+    if output.len() as u64 > max_output {
+        return Err(AnkiError::DecompressionError(format!(
+            "decompressed size exceeds the limit of {} bytes",
+            max_output
+        )));
+    }
+
+    Ok(output)
 }
 
 /// Validate that data looks like a valid image based on magic bytes
@@ -100,6 +157,75 @@ pub fn is_valid_image(data: &[u8]) -> bool {
     false
 }
 
+/// Read an image's pixel size from its header without decoding it
+/// Supports PNG, JPEG, GIF and WebP; anything else returns None
+pub fn image_dimensions(data: &[u8]) -> Option<ImageDimensions> {
+    let le16 = |at: usize| Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le24 = |at: usize| {
+        let bytes = data.get(at..at + 3)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+    };
+
+    let (width, height) = if data.starts_with(&magic::PNG) {
+        // The IHDR chunk always comes first: width and height at 16..24
+        let width = u32::from_be_bytes(data.get(16..20)?.try_into().ok()?);
+        let height = u32::from_be_bytes(data.get(20..24)?.try_into().ok()?);
+        (width, height)
+    } else if data.starts_with(&magic::GIF87) || data.starts_with(&magic::GIF89) {
+        (le16(6)?, le16(8)?)
+    } else if data.starts_with(&magic::JPEG) {
+        jpeg_dimensions(data)?
+    } else if data.starts_with(&magic::WEBP) && data.get(8..12) == Some(b"WEBP") {
+        match data.get(12..16)? {
+            // Lossy: 3-byte frame tag and 3-byte start code precede 14-bit sizes
+            b"VP8 " => (le16(26)? & 0x3FFF, le16(28)? & 0x3FFF),
+            // Lossless: signature byte, then 14 bits each of width - 1 and height - 1
+            b"VP8L" => {
+                let bits = u32::from_le_bytes(data.get(21..25)?.try_into().ok()?);
+                ((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1)
+            }
+            // Extended: 24-bit canvas width - 1 and height - 1 after the flags
+            b"VP8X" => (le24(24)? + 1, le24(27)? + 1),
+            _ => return None,
+        }
+    } else {
+        return None;
+    };
+
+    Some(ImageDimensions { width, height })
+}
+
+/// Find the first start-of-frame segment in a JPEG and read its size
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    let be16 = |at: usize| Some(u16::from_be_bytes(data.get(at..at + 2)?.try_into().ok()?) as u32);
+
+    let mut pos = 2;
+    loop {
+        // Markers may be padded with any number of 0xFF fill bytes
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        while *data.get(pos)? == 0xFF {
+            pos += 1;
+        }
+        let marker = *data.get(pos)?;
+        pos += 1;
+
+        match marker {
+            // Standalone markers carry no length
+            0x01 | 0xD0..=0xD7 => continue,
+            // SOF0-SOF15, except DHT (C4), JPG (C8) and DAC (CC)
+            0xC0..=0xCF if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                // Length, precision, then height and width
+                return Some((be16(pos + 5)?, be16(pos + 3)?));
+            }
+            // End of image or start of scan before any frame header
+            0xD9 | 0xDA => return None,
+            _ => pos += be16(pos)? as usize,
+        }
+    }
+}
+
 /// Validate that data looks like valid audio based on magic bytes
 pub fn is_valid_audio(data: &[u8]) -> bool {
     if data.len() < 4 {
@@ -139,18 +265,243 @@ pub fn is_valid_audio(data: &[u8]) -> bool {
     false
 }
 
+/// Read an audio clip's duration from its headers
+/// Supports MP3, Ogg (Vorbis and Opus) and WAV; anything else returns None
+pub fn audio_duration_ms(data: &[u8]) -> Option<u64> {
+    if data.starts_with(&magic::OGG) {
+        ogg_duration_ms(data)
+    } else if data.starts_with(&magic::WAV) && data.get(8..12) == Some(b"WAVE") {
+        wav_duration_ms(data)
+    } else {
+        mp3_duration_ms(data)
+    }
+}
+
+/// Sum the samples in every MPEG audio frame
+fn mp3_duration_ms(data: &[u8]) -> Option<u64> {
+    // Skip an ID3v2 tag: 10-byte header with a syncsafe (7 bits per byte) size
+    let mut pos = 0;
+    if data.starts_with(&magic::MP3_ID3) {
+        let size = data.get(6..10)?;
+        pos = 10 + size.iter().fold(0usize, |acc, &b| (acc << 7) | (b & 0x7F) as usize);
+    }
+
+    let mut samples = 0u64;
+    let mut sample_rate = 0u32;
+    let mut first = true;
+    while let Some(header) = data.get(pos..pos + 4) {
+        let Some(frame) = Mp3Frame::parse(header) else {
+            break;
+        };
+        // A Xing/Info frame describes the stream and holds no audio
+        let frame_data = &data[pos..(pos + frame.length).min(data.len())];
+        let is_info = first && frame_data.windows(4).any(|w| w == b"Xing" || w == b"Info");
+        if !is_info {
+            samples += frame.samples as u64;
+        }
+        sample_rate = frame.sample_rate;
+        first = false;
+        pos += frame.length;
+    }
+
+    if sample_rate == 0 {
+        return None;
+    }
+    Some(samples * 1000 / sample_rate as u64)
+}
+
+/// The parts of an MPEG audio frame header needed to walk a stream
+struct Mp3Frame {
+    length: usize,
+    samples: u32,
+    sample_rate: u32,
+}
+
+impl Mp3Frame {
+    fn parse(header: &[u8]) -> Option<Self> {
+        if header[0] != 0xFF || header[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        // Version: 0 = MPEG 2.5, 2 = MPEG 2, 3 = MPEG 1; layer: 1 = III, 2 = II, 3 = I
+        let version = (header[1] >> 3) & 0x03;
+        let layer = (header[1] >> 1) & 0x03;
+        let bitrate_index = (header[2] >> 4) as usize;
+        let rate_index = ((header[2] >> 2) & 0x03) as usize;
+        let padding = ((header[2] >> 1) & 0x01) as usize;
+        let reserved = version == 1 || layer == 0 || rate_index == 3;
+        if reserved || bitrate_index == 0 || bitrate_index == 15 {
+            return None;
+        }
+
+        const BITRATES: [[u32; 14]; 5] = [
+            [32, 64, 96, 128, 160, 192, 224, 256, 288, 320, 352, 384, 416, 448],
+            [32, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320, 384],
+            [32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320],
+            [32, 48, 56, 64, 80, 96, 112, 128, 144, 160, 176, 192, 224, 256],
+            [8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160],
+        ];
+        let mpeg1 = version == 3;
+        let table = match (mpeg1, layer) {
+            (true, 3) => 0,
+            (true, 2) => 1,
+            (true, _) => 2,
+            (false, 3) => 3,
+            (false, _) => 4,
+        };
+        let bitrate = BITRATES[table][bitrate_index - 1] * 1000;
+
+        // MPEG 2 halves the MPEG 1 sample rates, MPEG 2.5 quarters them
+        let divisor = match version {
+            3 => 1,
+            2 => 2,
+            _ => 4,
+        };
+        let sample_rate = [44100, 48000, 32000][rate_index] / divisor;
+        let samples = match layer {
+            3 => 384,
+            2 => 1152,
+            _ if mpeg1 => 1152,
+            _ => 576,
+        };
+        // Layer I pads with a 4-byte slot, the others with a single byte
+        let length = if layer == 3 {
+            ((12 * bitrate / sample_rate) as usize + padding) * 4
+        } else {
+            (samples / 8 * bitrate / sample_rate) as usize + padding
+        };
+
+        Some(Self { length: length.max(4), samples, sample_rate })
+    }
+}
+
+/// Divide the last page's granule position by the stream's sample rate
+fn ogg_duration_ms(data: &[u8]) -> Option<u64> {
+    // The first page holds the codec identification header
+    let segments = *data.get(26)? as usize;
+    let packet = data.get(27 + segments..)?;
+    let (sample_rate, pre_skip) = if packet.starts_with(b"\x01vorbis") {
+        (u32::from_le_bytes(packet.get(12..16)?.try_into().ok()?) as u64, 0)
+    } else if packet.starts_with(b"OpusHead") {
+        // Opus granule positions always count 48 kHz samples
+        (48_000, u16::from_le_bytes(packet.get(10..12)?.try_into().ok()?) as u64)
+    } else {
+        return None;
+    };
+    if sample_rate == 0 {
+        return None;
+    }
+
+    let last_page = (0..=data.len().saturating_sub(27))
+        .rev()
+        .find(|&i| data[i..].starts_with(&magic::OGG) && data[i + 4] == 0)?;
+    let granule = u64::from_le_bytes(data.get(last_page + 6..last_page + 14)?.try_into().ok()?);
+    Some(granule.saturating_sub(pre_skip) * 1000 / sample_rate)
+}
+
+/// Divide the `data` chunk's size by the byte rate in the `fmt ` chunk
+fn wav_duration_ms(data: &[u8]) -> Option<u64> {
+    let mut byte_rate = None;
+    let mut pos = 12;
+    while let Some(header) = data.get(pos..pos + 8) {
+        let size = u32::from_le_bytes(header[4..8].try_into().ok()?) as usize;
+        let body = pos + 8;
+        match &header[..4] {
+            b"fmt " => {
+                let rate = data.get(body + 8..body + 12)?;
+                byte_rate = Some(u32::from_le_bytes(rate.try_into().ok()?));
+            }
+            b"data" => {
+                let byte_rate = byte_rate.filter(|&rate| rate > 0)? as u64;
+                return Some(size as u64 * 1000 / byte_rate);
+            }
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        pos = body + size + (size & 1);
+    }
+    None
+}
+
+/// Read the media mapping (index -> filename) in whichever format the package uses
+///
+/// Latest-version packages store it as a protobuf blob, everything else as JSON;
+/// a blob that decompresses to more than `max_bytes` is rejected.
+pub fn extract_media_mapping(
+    archive: &mut AnkiArchive,
+    max_bytes: u64,
+) -> Result<HashMap<String, String>, AnkiError> {
+    match archive.read_package_meta() {
+        Some(meta) if meta.media_is_blob => archive.extract_media_entries(max_bytes),
+        _ => archive.extract_media_mapping(),
+    }
+}
+
+/// Media types extracted when `ParseOptions.media_types` is not set
+const DEFAULT_MEDIA_TYPES: &[MediaType] = &[MediaType::Audio, MediaType::Image];
+
 /// Process media files from the archive
+///
+/// Only files of `options.media_types` (audio and images by default) are extracted.
+/// Files that decompress to more than `options.max_media_bytes` are skipped.
+/// Fails with `AnkiError::Timeout` once `deadline` passes.
 pub fn process_media<F>(
     archive: &mut AnkiArchive,
+    options: &ParseOptions,
+    deadline: Option<Instant>,
+    progress_callback: F,
+) -> Result<Arc<AnkiMediaStore>, AnkiError>
+where
+    F: FnMut(usize, usize),
+{
+    let media_types = options.media_types.as_deref().unwrap_or(DEFAULT_MEDIA_TYPES);
+    process_media_matching(
+        archive,
+        options,
+        deadline,
+        |_, media_type| media_types.contains(&media_type),
+        progress_callback,
+    )
+}
+
+/// Process only the media files named in `wanted`, whatever their type
+///
+/// Names are matched exactly against the media mapping. Otherwise behaves like
+/// `process_media`.
+pub fn process_media_filtered<F>(
+    archive: &mut AnkiArchive,
+    wanted: &HashSet<String>,
+    options: &ParseOptions,
+    deadline: Option<Instant>,
+    progress_callback: F,
+) -> Result<Arc<AnkiMediaStore>, AnkiError>
+where
+    F: FnMut(usize, usize),
+{
+    process_media_matching(
+        archive,
+        options,
+        deadline,
+        |filename, _| wanted.contains(filename),
+        progress_callback,
+    )
+}
+
+/// Extract, decompress and validate the media files accepted by `wanted`
+fn process_media_matching<W, F>(
+    archive: &mut AnkiArchive,
+    options: &ParseOptions,
+    deadline: Option<Instant>,
+    wanted: W,
     mut progress_callback: F,
 ) -> Result<Arc<AnkiMediaStore>, AnkiError>
 where
+    W: Fn(&str, MediaType) -> bool,
     F: FnMut(usize, usize),
 {
     let store = Arc::new(AnkiMediaStore::new());
 
     // Get media mapping (index -> filename)
-    let mapping = archive.extract_media_mapping()?;
+    let mapping = extract_media_mapping(archive, options.max_media_bytes)?;
     let total = mapping.len();
 
     if total == 0 {
@@ -160,37 +511,56 @@ where
     let mut current = 0;
 
     for (index, filename) in &mapping {
-        // Only process audio and image files
+        check_deadline(deadline)?;
+
+        // Skip files that weren't asked for without reading them
         let media_type = media_type_from_extension(filename);
-        if media_type == MediaType::Unknown {
+        if !wanted(filename, media_type) {
             current += 1;
             continue;
         }
 
         // Extract the file data
-        if let Some(mut data) = archive.extract_media(index)? {
-            // Decompress if zstd-compressed
-            if is_zstd_compressed(&data) {
-                match decompress_zstd(&data) {
-                    Ok(decompressed) => {
-                        data = decompressed;
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to decompress {}: {}", filename, e);
-                        // Skip this file
-                        current += 1;
-                        continue;
-                    }
+        if let Some(mut data) = archive.extract_file_by_index(index)? {
+            // Decompress if zstd- or (in very old exports) gzip-compressed
+            let decompressed = if is_zstd_compressed(&data) {
+                Some(decompress_zstd_limited(&data, options.max_media_bytes))
+            } else if is_gzip_compressed(&data) {
+                Some(decompress_gzip_limited(&data, options.max_media_bytes))
+            } else {
+                None
+            };
+            match decompressed {
+                Some(Ok(decompressed)) => {
+                    data = decompressed;
                 }
+                Some(Err(e)) => {
+                    log::warn!("Failed to decompress {}: {}", filename, e);
+                    options.emit(ParseEvent::MediaDecompressFailed {
+                        filename: filename.clone(),
+                        reason: e.to_string(),
+                    });
+                    // Skip this file
+                    current += 1;
+                    continue;
+                }
+                None => {}
             }
 
             // Validate the file
+            // Video and unknown files have no header check
             let is_valid = match media_type {
                 MediaType::Image => is_valid_image(&data),
                 MediaType::Audio => is_valid_audio(&data),
-                MediaType::Unknown => false,
+                MediaType::Video | MediaType::Unknown => true,
             };
 
+            // Let `resolve` find the file by its entry name, or without a folder prefix
+            store.add_alias(index.clone(), filename.clone());
+            if let Some((_, base)) = filename.rsplit_once(['/', '\\']) {
+                store.add_alias(base.to_string(), filename.clone());
+            }
+
             if is_valid {
                 store.insert(filename.clone(), data);
             } else {
@@ -199,6 +569,7 @@ where
                     "Media file {} may be invalid (header check failed)",
                     filename
                 );
+                options.emit(ParseEvent::MediaValidationFailed { filename: filename.clone() });
                 store.insert(filename.clone(), data);
             }
         }
@@ -226,6 +597,7 @@ mod tests {
         assert_eq!(media_type_from_extension("audio.mp3"), MediaType::Audio);
         assert_eq!(media_type_from_extension("image.jpg"), MediaType::Image);
         assert_eq!(media_type_from_extension("image.PNG"), MediaType::Image);
+        assert_eq!(media_type_from_extension("clip.mp4"), MediaType::Video);
         assert_eq!(media_type_from_extension("unknown.xyz"), MediaType::Unknown);
     }
 
@@ -238,6 +610,40 @@ mod tests {
         assert!(!is_zstd_compressed(&regular_data));
     }
 
+    #[test]
+    fn test_decompress_zstd_limited() {
+        let data = vec![0u8; 1024 * 1024];
+        let compressed = zstd::encode_all(&data[..], 0).unwrap();
+        assert!(compressed.len() < 1024);
+
+        assert_eq!(decompress_zstd_limited(&compressed, data.len() as u64).unwrap(), data);
+        assert!(matches!(
+            decompress_zstd_limited(&compressed, 1000),
+            Err(AnkiError::DecompressionError(_))
+        ));
+    }
+
+    #[test]
+    fn test_decompress_gzip_limited() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&[7u8; 4096]).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert!(is_gzip_compressed(&compressed));
+        assert_eq!(decompress_gzip_limited(&compressed, 4096).unwrap(), [7u8; 4096]);
+        assert!(matches!(
+            decompress_gzip_limited(&compressed, 100),
+            Err(AnkiError::DecompressionError(_))
+        ));
+        assert!(matches!(
+            decompress_gzip_limited(&[0x1F, 0x8B, 0xFF], 100),
+            Err(AnkiError::DecompressionError(_))
+        ));
+    }
+
     #[test]
     fn test_image_validation() {
         // JPEG
@@ -253,6 +659,151 @@ mod tests {
         assert!(!is_valid_image(&invalid));
     }
 
+    #[test]
+    fn test_image_dimensions() {
+        let size = |width, height| Some(ImageDimensions { width, height });
+
+        let mut png = magic::PNG.to_vec();
+        png.extend([0, 0, 0, 13]);
+        png.extend(b"IHDR");
+        png.extend(640u32.to_be_bytes());
+        png.extend(480u32.to_be_bytes());
+        assert_eq!(image_dimensions(&png), size(640, 480));
+
+        let mut gif = magic::GIF89.to_vec();
+        gif.extend([0x20, 0x01, 0x10, 0x00]);
+        assert_eq!(image_dimensions(&gif), size(288, 16));
+
+        // APP0 segment, then a baseline frame header (0xFFC0) with fill bytes before it
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, 0xFF, 0xFF, 0xC0];
+        jpeg.extend([0x00, 0x11, 0x08, 0x00, 0x78, 0x00, 0xA0]);
+        assert_eq!(image_dimensions(&jpeg), size(160, 120));
+
+        let webp = |chunk: &[u8], payload: &[u8]| {
+            let mut data = b"RIFF\0\0\0\0WEBP".to_vec();
+            data.extend(chunk);
+            data.extend([0; 4]);
+            data.extend(payload);
+            data
+        };
+        let lossy = webp(b"VP8 ", &[0, 0, 0, 0x9D, 0x01, 0x2A, 0x64, 0x00, 0x32, 0x00]);
+        assert_eq!(image_dimensions(&lossy), size(100, 50));
+        let bits: u32 = 99 | (49 << 14);
+        let mut lossless_payload = vec![0x2F];
+        lossless_payload.extend(bits.to_le_bytes());
+        assert_eq!(image_dimensions(&webp(b"VP8L", &lossless_payload)), size(100, 50));
+        let extended = webp(b"VP8X", &[0, 0, 0, 0, 0xFF, 0x03, 0x00, 0xFF, 0x01, 0x00]);
+        assert_eq!(image_dimensions(&extended), size(1024, 512));
+
+        assert_eq!(image_dimensions(&magic::PNG), None);
+        assert_eq!(image_dimensions(b"ID3\x04\0\0\0\0\0\0"), None);
+    }
+
+    #[test]
+    fn test_audio_duration() {
+        // MPEG 1 Layer III, 128 kbps, 44.1 kHz: 417-byte frames of 1152 samples
+        let frame = |tag: &[u8]| {
+            let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+            frame.extend(tag);
+            frame.resize(417, 0);
+            frame
+        };
+        let mut mp3 = b"ID3\x04\0\0\0\0\0\x02\0\0".to_vec();
+        for _ in 0..10 {
+            mp3.extend(frame(b""));
+        }
+        assert_eq!(audio_duration_ms(&mp3), Some(11520 * 1000 / 44100));
+
+        // A leading Info frame holds no audio
+        let mut vbr = frame(b"\0\0\0\0Info");
+        vbr.extend(frame(b""));
+        assert_eq!(audio_duration_ms(&vbr), Some(1152 * 1000 / 44100));
+
+        // 8 kHz mono 16-bit: 16000 bytes per second, after an odd-sized chunk
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0\x01\0\x01\0".to_vec();
+        wav.extend(8000u32.to_le_bytes());
+        wav.extend(16000u32.to_le_bytes());
+        wav.extend([2, 0, 16, 0]);
+        wav.extend(b"LIST\x03\0\0\0abc\0");
+        wav.extend(b"data");
+        wav.extend(32000u32.to_le_bytes());
+        assert_eq!(audio_duration_ms(&wav), Some(2000));
+
+        let ogg_page = |granule: u64, packet: &[u8]| {
+            let mut page = b"OggS\0\0".to_vec();
+            page.extend(granule.to_le_bytes());
+            page.extend([0; 12]);
+            page.push(1);
+            page.push(packet.len() as u8);
+            page.extend(packet);
+            page
+        };
+        let mut vorbis_header = b"\x01vorbis\0\0\0\0\x02".to_vec();
+        vorbis_header.extend(44100u32.to_le_bytes());
+        let mut vorbis = ogg_page(0, &vorbis_header);
+        vorbis.extend(ogg_page(88200, b"audio"));
+        assert_eq!(audio_duration_ms(&vorbis), Some(2000));
+
+        // Opus counts 48 kHz samples and starts after a pre-skip
+        let mut opus = ogg_page(0, b"OpusHead\x01\x02\x38\x01");
+        opus.extend(ogg_page(96000 + 312, b"audio"));
+        assert_eq!(audio_duration_ms(&opus), Some(2000));
+
+        assert_eq!(audio_duration_ms(b"fLaC\0\0\0\0"), None);
+        assert_eq!(audio_duration_ms(&magic::PNG), None);
+    }
+
+    #[test]
+    fn test_process_media_aliases() {
+        let png = b"\x89PNG\r\n\x1a\nimage data";
+        let data = crate::archive::tests::zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "img/a.png"}"#),
+            ("0", png),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        let store = process_media(&mut archive, &ParseOptions::default(), None, |_, _| {}).unwrap();
+
+        assert_eq!(store.resolve("img/a.png".to_string()).unwrap(), png);
+        assert_eq!(store.resolve("a.png".to_string()).unwrap(), png);
+        assert_eq!(store.resolve("0".to_string()).unwrap(), png);
+        assert_eq!(store.data_for("a.png".to_string()), None);
+    }
+
+    #[test]
+    fn test_process_media_filters() {
+        let png = b"\x89PNG\r\n\x1a\nimage data";
+        let data = crate::archive::tests::zip_bytes(&[
+            ("collection.anki2", b"db"),
+            ("media", br#"{"0": "a.png", "1": "b.mp3", "2": "c.mp4"}"#),
+            ("0", png),
+            ("1", b"ID3audio"),
+            ("2", b"video"),
+        ]);
+        let mut archive = AnkiArchive::from_bytes(data).unwrap();
+        let sorted = |store: Arc<AnkiMediaStore>| {
+            let mut names = store.filenames();
+            names.sort();
+            names
+        };
+
+        let store = process_media(&mut archive, &ParseOptions::default(), None, |_, _| {}).unwrap();
+        assert_eq!(sorted(store), vec!["a.png", "b.mp3"]);
+
+        let options = ParseOptions {
+            media_types: Some(vec![MediaType::Audio, MediaType::Video]),
+            ..Default::default()
+        };
+        let store = process_media(&mut archive, &options, None, |_, _| {}).unwrap();
+        assert_eq!(sorted(store), vec!["b.mp3", "c.mp4"]);
+
+        let wanted = HashSet::from(["c.mp4".to_string(), "a.png".to_string()]);
+        let store =
+            process_media_filtered(&mut archive, &wanted, &ParseOptions::default(), None, |_, _| {})
+                .unwrap();
+        assert_eq!(sorted(store), vec!["a.png", "c.mp4"]);
+    }
+
     #[test]
     fn test_audio_validation() {
         // MP3 with ID3
@@ -1,10 +1,24 @@
-use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use unicode_normalization::UnicodeNormalization;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::archive::{AnkiFormat, DEFAULT_MAX_DATABASE_BYTES};
+use crate::html::percent_decode;
+use crate::media::{
+    audio_duration_ms, image_dimensions, media_type_from_extension, MediaType,
+    DEFAULT_MAX_MEDIA_BYTES,
+};
 
 /// Progress states during parsing
 #[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Enum)]
 pub enum AnkiProgress {
     Extracting,
+    /// Decompressing a `collection.anki21b` database; skipped for other formats
+    DecompressingDatabase,
+    OpeningDatabase,
     ReadingDecks,
     ReadingCards,
     ProcessingMedia,
@@ -17,6 +31,179 @@ pub trait AnkiProgressCallback: Send + Sync {
     fn on_progress(&self, progress: AnkiProgress);
 }
 
+/// A notable thing that happened while parsing, for machine-readable diagnostics
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Enum)]
+pub enum ParseEvent {
+    /// A media file could not be decompressed (or exceeded the size cap) and was skipped
+    MediaDecompressFailed { filename: String, reason: String },
+    /// A media file failed its header check; it is still included
+    MediaValidationFailed { filename: String },
+    /// Cards referenced a deck that doesn't exist, so a placeholder was created
+    OrphanDeckCreated { id: i64 },
+    /// Cards whose note row is missing from the collection
+    NotesMissing { count: u32 },
+}
+
+/// Machine-readable summary of a parse
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct ParseReport {
+    /// Archive format the collection was read from (None if not parsed from an archive)
+    pub format: Option<AnkiFormat>,
+    pub deck_count: u32,
+    pub card_count: u32,
+    /// Number of media files kept, keyed by `MediaType::as_str`
+    pub media_counts: HashMap<String, u32>,
+    /// Placeholder decks created for cards whose deck was missing
+    pub orphan_decks_created: u32,
+    /// Cards whose note row was missing
+    pub missing_notes: u32,
+    /// Media files skipped because they couldn't be decompressed
+    pub failed_media: u32,
+}
+
+impl ParseReport {
+    /// Tally an event into the report
+    pub fn record(&mut self, event: &ParseEvent) {
+        match event {
+            ParseEvent::MediaDecompressFailed { .. } => self.failed_media += 1,
+            ParseEvent::MediaValidationFailed { .. } => {}
+            ParseEvent::OrphanDeckCreated { .. } => self.orphan_decks_created += 1,
+            ParseEvent::NotesMissing { count } => self.missing_notes += count,
+        }
+    }
+}
+
+/// Receives `ParseEvent`s as they happen
+#[uniffi::export(with_foreign)]
+pub trait ParseEventSink: Send + Sync {
+    fn on_event(&self, event: ParseEvent);
+}
+
+/// How decks at the same hierarchy level are ordered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, uniffi::Enum)]
+pub enum DeckSortMode {
+    /// By full name
+    Alphabetical,
+    /// By `AnkiDeck.order` (the order decks were created in Anki)
+    #[default]
+    Original,
+}
+
+/// Options controlling how an Anki file is parsed
+#[derive(Clone, uniffi::Record)]
+pub struct ParseOptions {
+    /// Convert card fields and rendered sides to Markdown-like text with
+    /// `clean_html`. When false, `AnkiCard.fields` holds the raw HTML
+    #[uniffi(default = false)]
+    pub clean_html: bool,
+    /// Password for encrypted archives; ignored if the archive is not encrypted
+    #[uniffi(default = None)]
+    pub password: Option<String>,
+    /// Largest size a single media file may decompress to; bigger files are
+    /// skipped. Protects against zstd bombs in untrusted decks
+    #[uniffi(default = 268435456)]
+    pub max_media_bytes: u64,
+    /// Largest size the collection database may decompress to; parsing fails
+    /// with `AnkiError::DecompressionError` beyond it
+    #[uniffi(default = 2147483648)]
+    pub max_database_bytes: u64,
+    /// Abort with `AnkiError::Timeout` once parsing has run this long
+    #[uniffi(default = None)]
+    pub timeout_ms: Option<u64>,
+    /// Receives a `ParseEvent` for each notable thing that happens while parsing
+    #[uniffi(default = None)]
+    pub event_sink: Option<Arc<dyn ParseEventSink>>,
+    /// Try to repair fields from legacy decks that were stored as Latin-1 or
+    /// double-encoded ("cafÃ©" for "café"). Off by default since it is a heuristic
+    #[uniffi(default = false)]
+    pub repair_encoding: bool,
+    /// Only keep cards whose note has at least one of these tags (case-insensitive).
+    /// A tag also matches its subtags, so "lang" keeps "lang::jp". An empty
+    /// list keeps no cards; None keeps every card
+    #[uniffi(default = None)]
+    pub tag_filter: Option<Vec<String>>,
+    /// Deck hierarchy separator used by the exporter (e.g. "/" or ">"),
+    /// rewritten to Anki's "::" before deck names are split. None keeps "::"
+    #[uniffi(default = None)]
+    pub deck_separator: Option<String>,
+    /// Drop empty fields from the end of each card's `fields` (and `raw_fields`).
+    /// Interior empty fields are kept since fields are positional
+    #[uniffi(default = false)]
+    pub trim_empty_trailing_fields: bool,
+    /// Media types to extract; others are skipped without being read.
+    /// None extracts audio and images
+    #[uniffi(default = None)]
+    pub media_types: Option<Vec<MediaType>>,
+    /// Extra sound-tag regexes beyond `[sound:...]` and `[audio:...]`, whose first
+    /// capture group is the filename. Invalid patterns fail with `AnkiError::InvalidPattern`
+    #[uniffi(default = [])]
+    pub sound_patterns: Vec<String>,
+    /// With `clean_html`, collapse runs of spaces (including decoded `&nbsp;`
+    /// padding) into one. Code blocks keep their spacing
+    #[uniffi(default = false)]
+    pub collapse_spaces: bool,
+    /// Parse collections with a schema newer than `MAX_SCHEMA_VERSION` on a
+    /// best-effort basis instead of failing with `AnkiError::UnsupportedSchemaVersion`
+    #[uniffi(default = false)]
+    pub force: bool,
+}
+
+impl fmt::Debug for ParseOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ParseOptions")
+            .field("clean_html", &self.clean_html)
+            .field("password", &self.password.as_ref().map(|_| "<redacted>"))
+            .field("max_media_bytes", &self.max_media_bytes)
+            .field("max_database_bytes", &self.max_database_bytes)
+            .field("timeout_ms", &self.timeout_ms)
+            .field("event_sink", &self.event_sink.is_some())
+            .field("repair_encoding", &self.repair_encoding)
+            .field("tag_filter", &self.tag_filter)
+            .field("deck_separator", &self.deck_separator)
+            .field("trim_empty_trailing_fields", &self.trim_empty_trailing_fields)
+            .field("media_types", &self.media_types)
+            .field("sound_patterns", &self.sound_patterns)
+            .field("collapse_spaces", &self.collapse_spaces)
+            .field("force", &self.force)
+            .finish()
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Self {
+            clean_html: false,
+            password: None,
+            max_media_bytes: DEFAULT_MAX_MEDIA_BYTES,
+            max_database_bytes: DEFAULT_MAX_DATABASE_BYTES,
+            timeout_ms: None,
+            event_sink: None,
+            repair_encoding: false,
+            tag_filter: None,
+            deck_separator: None,
+            trim_empty_trailing_fields: false,
+            media_types: None,
+            sound_patterns: Vec::new(),
+            collapse_spaces: false,
+            force: false,
+        }
+    }
+}
+
+impl ParseOptions {
+    /// The instant parsing must finish by if it starts now, if there is a timeout
+    pub fn deadline(&self) -> Option<Instant> {
+        self.timeout_ms.map(|ms| Instant::now() + Duration::from_millis(ms))
+    }
+
+    /// Send an event to the event sink, if there is one
+    pub(crate) fn emit(&self, event: ParseEvent) {
+        if let Some(sink) = &self.event_sink {
+            sink.on_event(event);
+        }
+    }
+}
+
 /// Represents a deck with hierarchy support
 #[derive(Debug, Clone, uniffi::Record)]
 pub struct AnkiDeck {
@@ -25,46 +212,211 @@ pub struct AnkiDeck {
     pub name: String,
     /// Just the leaf name (e.g., "Grandchild")
     pub short_name: String,
+    /// Position in the source collection's ordering (creation order)
+    pub order: u32,
+    /// Nesting level: 0 for root decks, 2 for "Parent::Child::Grandchild"
+    pub depth: u32,
 }
 
 impl AnkiDeck {
     /// Create a deck from its ID and full name
     pub fn from_name(id: i64, name: String) -> Self {
-        let short_name = name
-            .rsplit("::")
-            .next()
-            .unwrap_or(&name)
-            .to_string();
+        let short_name = leaf_name(&name).to_string();
+        let depth = name.matches("::").count() as u32;
 
-        Self { id, name, short_name }
+        Self { id, name, short_name, order: 0, depth }
+    }
+
+    /// Nesting level, computed once from the name at construction
+    pub fn depth(&self) -> u32 {
+        self.depth
     }
 
     /// Check if this deck is a root deck (no parent)
     pub fn is_root(&self) -> bool {
-        !self.name.contains("::")
+        self.depth == 0
     }
 
     /// Get the parent path (e.g., "Parent::Child" for "Parent::Child::Grandchild")
     pub fn parent_path(&self) -> Option<String> {
-        let parts: Vec<&str> = self.name.split("::").collect();
-        if parts.len() > 1 {
-            Some(parts[..parts.len() - 1].join("::"))
-        } else {
-            None
-        }
+        parent_name(&self.name).map(str::to_string)
     }
 }
 
-/// Represents a single card with its fields
+/// Last "::" component of a hierarchical deck or tag name
+fn leaf_name(name: &str) -> &str {
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Everything before the last "::" of a deck or tag name, if nested
+fn parent_name(name: &str) -> Option<&str> {
+    name.rfind("::").map(|i| &name[..i])
+}
+
+/// A card template (question/answer format pair) within a note type
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct AnkiCardTemplate {
+    pub name: String,
+    /// Position within the note type; matches the card's ordinal
+    pub ordinal: u32,
+    /// Question template, e.g. "{{Front}}"
+    pub question_format: String,
+    /// Answer template, e.g. "{{FrontSide}}<hr id=answer>{{Back}}"
+    pub answer_format: String,
+}
+
+/// A note type ("model" in Anki) describing field names and card templates
 #[derive(Debug, Clone, uniffi::Record)]
+pub struct AnkiNoteType {
+    pub id: i64,
+    pub name: String,
+    /// Field names in positional order (matches `AnkiCard.fields`)
+    pub field_names: Vec<String>,
+    /// Card templates in ordinal order
+    pub templates: Vec<AnkiCardTemplate>,
+    /// Styling shared by the note type's templates
+    pub css: String,
+    /// Whether Anki marks this as a cloze note type, rather than inferring
+    /// it from the templates
+    pub is_cloze: bool,
+}
+
+/// A tag registered in the collection's `tags` table (Anki 2.1.41+)
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct TagInfo {
+    /// Full tag name with "::" separators (e.g., "Lang::Japanese")
+    pub name: String,
+    /// Whether the tag is expanded in Anki's browser sidebar
+    pub expanded: bool,
+}
+
+/// A deletion recorded in the collection's `graves` table
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct GraveEntry {
+    /// ID of the deleted card, note or deck
+    pub oid: i64,
+    /// What was deleted: 0 = card, 1 = note, 2 = deck
+    pub type_: i32,
+}
+
+/// A node in the tag hierarchy built by `AnkiCollection::tag_tree`
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct TagNode {
+    /// Full tag name with "::" separators (e.g., "Lang::Japanese")
+    pub name: String,
+    /// Just the leaf name (e.g., "Japanese")
+    pub short_name: String,
+    /// Cards tagged with this tag or any tag nested under it
+    pub card_count: u32,
+    /// Whether the tag is expanded in Anki's sidebar (true if not in the tags table)
+    pub expanded: bool,
+    /// Child tags, sorted case-insensitively
+    pub children: Vec<TagNode>,
+}
+
+impl AnkiNoteType {
+    /// Get the template used for a card with the given ordinal
+    pub fn template(&self, ordinal: u32) -> Option<&AnkiCardTemplate> {
+        self.templates.iter().find(|t| t.ordinal == ordinal)
+    }
+}
+
+/// Represents a single card with its fields
+#[derive(Debug, Clone, Default, uniffi::Record)]
 pub struct AnkiCard {
     pub id: i64,
     pub note_id: i64,
+    /// Note GUID, stable across exports (unlike `note_id`)
+    pub note_guid: String,
+    /// Home deck ID; for a card in a filtered deck, the deck it came from (`cards.odid`)
     pub deck_id: i64,
+    /// Filtered deck currently holding the card (`cards.did`), if any
+    pub filtered_deck_id: Option<i64>,
+    /// Note type ID (`mid`), see `AnkiNoteType`
+    pub note_type_id: i64,
+    /// Template ordinal (`cards.ord`): which card of the note this is, e.g. the
+    /// reverse card of a "Basic (and reversed)" note or cloze `c{ordinal + 1}`
+    pub ordinal: u32,
     /// Card fields (front, back, extra, etc.)
     pub fields: Vec<String>,
+    /// Original HTML of each field; empty unless `fields` were cleaned
+    pub raw_fields: Vec<String>,
+    /// The note type's designated sort field (what Anki shows in the browser)
+    pub sort_field: String,
+    /// Note tags (Anki stores them space-separated; "::" marks hierarchy)
+    pub tags: Vec<String>,
     /// Media file references found in the card
     pub media_references: Vec<String>,
+    /// Question side rendered from the card template (None if the note type is unknown)
+    pub question: Option<String>,
+    /// Answer side rendered from the card template (None if the note type is unknown)
+    pub answer: Option<String>,
+    /// True if the card's note row is missing from the collection (fields will be empty)
+    pub note_missing: bool,
+    /// User flag color: 0 = none, 1 = red, 2 = orange, 3 = green, 4 = blue,
+    /// 5 = pink, 6 = turquoise, 7 = purple
+    pub flag: u8,
+    /// Raw scheduler queue: -3/-2 = buried, -1 = suspended, 0 = new,
+    /// 1 = learning, 2 = review, 3 = day-learning, 4 = preview
+    pub queue: i32,
+    /// Due (`cards.due`): position for new cards, day number (see `schedule::day_number`)
+    /// for reviews, epoch seconds for (re)learning. The pre-filter due (`odue`) for
+    /// cards in a filtered deck
+    pub due: i64,
+    /// Suspended cards are excluded from study until unsuspended
+    pub is_suspended: bool,
+    /// Buried cards are hidden from study until the next day
+    pub is_buried: bool,
+    /// Card type (`cards.type`): 0 = new, 1 = learning, 2 = review, 3 = relearning
+    pub card_type: u8,
+    /// Never studied (`card_type` 0); suspended or buried new cards count too
+    pub is_new: bool,
+    /// Review interval (`cards.ivl`): days if positive, seconds if negative (learning)
+    pub interval: i32,
+    /// Last modification of the card (`cards.mod`), epoch seconds
+    pub card_modified: i64,
+    /// Last modification of the note (`notes.mod`), epoch seconds; 0 if the note is missing
+    pub note_modified: i64,
+}
+
+impl AnkiCard {
+    /// When the card was created, in epoch seconds
+    /// Anki card IDs are the creation time in epoch milliseconds
+    pub fn created_at(&self) -> i64 {
+        self.id / 1000
+    }
+}
+
+/// Collection-level scheduling metadata, needed to compute Anki's day boundaries
+#[derive(Debug, Clone, PartialEq, Eq, uniffi::Record)]
+pub struct CollectionMeta {
+    /// Collection creation time (`col.crt`, seconds since epoch)
+    pub created_at: i64,
+    /// Scheduler version (`schedVer`): 1, 2 or 3
+    pub scheduler_version: u32,
+    /// Hour of the day (local time) at which a new study day starts
+    pub rollover_hour: u8,
+    /// Timezone offset at creation, in minutes west of UTC (`creationOffset`)
+    pub creation_offset: Option<i32>,
+}
+
+impl Default for CollectionMeta {
+    fn default() -> Self {
+        // Anki's defaults when the config keys are absent
+        Self {
+            created_at: 0,
+            scheduler_version: 1,
+            rollover_hour: 4,
+            creation_offset: None,
+        }
+    }
+}
+
+/// Pixel size of an image, read from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, uniffi::Record)]
+pub struct ImageDimensions {
+    pub width: u32,
+    pub height: u32,
 }
 
 /// Media store for accessing media files
@@ -74,6 +426,14 @@ pub struct AnkiMediaStore {
     data: RwLock<HashMap<String, Vec<u8>>>,
     /// Ordered list of filenames
     filenames_list: RwLock<Vec<String>>,
+    /// Cached content hashes, computed on first request
+    fingerprints: RwLock<HashMap<String, String>>,
+    /// Cached image sizes (None for non-images), computed on first request
+    dimensions: RwLock<HashMap<String, Option<ImageDimensions>>>,
+    /// Lowercased filename -> stored filename, built on the first case-insensitive lookup
+    lowercase_index: RwLock<Option<HashMap<String, String>>>,
+    /// Other names a file is known by (lowercased), e.g. its archive entry name
+    aliases: RwLock<HashMap<String, String>>,
 }
 
 impl AnkiMediaStore {
@@ -81,22 +441,39 @@ impl AnkiMediaStore {
         Self {
             data: RwLock::new(HashMap::new()),
             filenames_list: RwLock::new(Vec::new()),
+            fingerprints: RwLock::new(HashMap::new()),
+            dimensions: RwLock::new(HashMap::new()),
+            lowercase_index: RwLock::new(None),
+            aliases: RwLock::new(HashMap::new()),
         }
     }
 
     /// Add media data to the store
     pub fn insert(&self, filename: String, data: Vec<u8>) {
+        let filename = media_key(&filename);
         let mut store = self.data.write().unwrap();
         let mut filenames = self.filenames_list.write().unwrap();
 
         if !store.contains_key(&filename) {
             filenames.push(filename.clone());
         }
+        self.fingerprints.write().unwrap().remove(&filename);
+        self.dimensions.write().unwrap().remove(&filename);
+        *self.lowercase_index.write().unwrap() = None;
         store.insert(filename, data);
     }
 
+    /// Register another name that `resolve` should map to `filename`
+    pub fn add_alias(&self, alias: String, filename: String) {
+        self.aliases
+            .write()
+            .unwrap()
+            .insert(media_key(&alias).to_lowercase(), media_key(&filename));
+    }
+
     /// Add just the filename (for lazy loading)
     pub fn add_filename(&self, filename: String) {
+        let filename = media_key(&filename);
         let store = self.data.read().unwrap();
         let mut filenames = self.filenames_list.write().unwrap();
 
@@ -114,14 +491,152 @@ impl AnkiMediaStore {
     }
 
     /// Get data for a specific media file
+    /// Falls back to a case-insensitive match when there is no exact one
     pub fn data_for(&self, filename: String) -> Option<Vec<u8>> {
-        self.data.read().unwrap().get(&filename).cloned()
+        let filename = media_key(&filename);
+        let store = self.data.read().unwrap();
+        if let Some(data) = store.get(&filename) {
+            return Some(data.clone());
+        }
+
+        let lowercase = filename.to_lowercase();
+        if let Some(index) = self.lowercase_index.read().unwrap().as_ref() {
+            return index.get(&lowercase).and_then(|key| store.get(key)).cloned();
+        }
+
+        let mut index = self.lowercase_index.write().unwrap();
+        let index = index.get_or_insert_with(|| {
+            store.keys().map(|key| (key.to_lowercase(), key.clone())).collect()
+        });
+        index.get(&lowercase).and_then(|key| store.get(key)).cloned()
+    }
+
+    /// Find the data for a filename as referenced by a card
+    ///
+    /// Tries `data_for` (exact, then Unicode-normalized and case-insensitive
+    /// matches), then names registered while processing media, such as the
+    /// numbered archive entry or a path-less form. Percent-encoded references
+    /// ("my%20image.png") are decoded and tried the same way.
+    pub fn resolve(&self, reference: String) -> Option<Vec<u8>> {
+        let decoded = percent_decode(&reference);
+        [reference, decoded].into_iter().find_map(|candidate| {
+            self.data_for(candidate.clone()).or_else(|| {
+                let key = media_key(&candidate).to_lowercase();
+                let filename = self.aliases.read().unwrap().get(&key).cloned()?;
+                self.data_for(filename)
+            })
+        })
     }
 
     /// Get the number of media files
     pub fn count(&self) -> u32 {
         self.filenames_list.read().unwrap().len() as u32
     }
+
+    /// Remove a media file, returning its data if any was stored
+    /// The filename is dropped from the list too
+    pub fn remove(&self, filename: String) -> Option<Vec<u8>> {
+        let filename = media_key(&filename);
+        let mut store = self.data.write().unwrap();
+        let mut filenames = self.filenames_list.write().unwrap();
+
+        filenames.retain(|f| *f != filename);
+        self.fingerprints.write().unwrap().remove(&filename);
+        self.dimensions.write().unwrap().remove(&filename);
+        *self.lowercase_index.write().unwrap() = None;
+        store.remove(&filename)
+    }
+
+    /// Drop all stored media data while keeping the filename list
+    /// Useful once media has been written to disk
+    pub fn clear_data(&self) {
+        let mut store = self.data.write().unwrap();
+        store.clear();
+        store.shrink_to_fit();
+        self.fingerprints.write().unwrap().clear();
+        self.dimensions.write().unwrap().clear();
+        *self.lowercase_index.write().unwrap() = None;
+    }
+
+    /// Get the total size of all stored media in bytes
+    pub fn total_bytes(&self) -> u64 {
+        self.data.read().unwrap().values().map(|d| d.len() as u64).sum()
+    }
+
+    /// Get stored media size in bytes grouped by type
+    /// Keys are "audio", "image", "video" and "unknown"
+    pub fn bytes_by_type(&self) -> HashMap<String, u64> {
+        let mut totals = HashMap::new();
+        for (filename, data) in self.data.read().unwrap().iter() {
+            let media_type = media_type_from_extension(filename);
+            *totals.entry(media_type.as_str().to_string()).or_insert(0) += data.len() as u64;
+        }
+        totals
+    }
+
+    /// Number of files per media type, keyed by `MediaType::as_str`
+    pub fn count_by_type(&self) -> HashMap<String, u32> {
+        let mut counts = HashMap::new();
+        for filename in self.data.read().unwrap().keys() {
+            let media_type = media_type_from_extension(filename);
+            *counts.entry(media_type.as_str().to_string()).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Get a stable hex hash of a media file's contents
+    /// Meant for change detection between imports, not for security
+    pub fn fingerprint(&self, filename: String) -> Option<String> {
+        let filename = media_key(&filename);
+        if let Some(hash) = self.fingerprints.read().unwrap().get(&filename) {
+            return Some(hash.clone());
+        }
+
+        // Hold the data lock while caching so a concurrent insert can't leave a stale hash
+        let store = self.data.read().unwrap();
+        let hash = format!("{:016x}", xxh3_64(store.get(&filename)?));
+        self.fingerprints.write().unwrap().insert(filename, hash.clone());
+        Some(hash)
+    }
+
+    /// Get an image's width and height from its header, without decoding it
+    /// Supports PNG, JPEG, GIF and WebP; None for other formats or missing files
+    pub fn image_dimensions(&self, filename: String) -> Option<ImageDimensions> {
+        let filename = media_key(&filename);
+        if let Some(dimensions) = self.dimensions.read().unwrap().get(&filename) {
+            return *dimensions;
+        }
+
+        let store = self.data.read().unwrap();
+        let dimensions = image_dimensions(store.get(&filename)?);
+        self.dimensions.write().unwrap().insert(filename, dimensions);
+        dimensions
+    }
+
+    /// Get an audio clip's duration in milliseconds from its headers
+    /// Supports MP3, Ogg (Vorbis and Opus) and WAV; None for other formats or missing files
+    pub fn audio_duration_ms(&self, filename: String) -> Option<u64> {
+        let store = self.data.read().unwrap();
+        audio_duration_ms(store.get(&media_key(&filename))?)
+    }
+
+    /// Get fingerprints for every stored media file, keyed by filename
+    pub fn all_fingerprints(&self) -> HashMap<String, String> {
+        let filenames: Vec<String> = self.data.read().unwrap().keys().cloned().collect();
+        filenames
+            .into_iter()
+            .filter_map(|filename| {
+                let hash = self.fingerprint(filename.clone())?;
+                Some((filename, hash))
+            })
+            .collect()
+    }
+}
+
+/// Store media under NFC-normalized names, so lookups match whichever
+/// normalization the filesystem or the collection used
+fn media_key(filename: &str) -> String {
+    filename.nfc().collect()
 }
 
 impl Default for AnkiMediaStore {
@@ -130,17 +645,64 @@ impl Default for AnkiMediaStore {
     }
 }
 
+/// Quick overview of an archive, gathered without a full parse
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct CollectionSummary {
+    /// Number of decks (including subdecks)
+    pub deck_count: u32,
+    /// Number of cards
+    pub card_count: u32,
+    /// Number of cards per deck ID (as string key for UniFFI compatibility)
+    pub card_counts_by_deck: HashMap<String, u32>,
+    /// Number of entries in the media mapping
+    pub media_count: u32,
+    /// Total size of media entries as stored in the archive (compressed)
+    pub media_bytes: u64,
+}
+
+/// Review cards with an interval of at least this many days count as mature,
+/// matching Anki's statistics
+pub const MATURE_INTERVAL_DAYS: i32 = 21;
+
+/// Anki-style card counts for a deck overview
+#[derive(Debug, Clone, Default, PartialEq, Eq, uniffi::Record)]
+pub struct DeckStats {
+    /// Cards never studied
+    pub new: u32,
+    /// Cards in (re)learning steps
+    pub learning: u32,
+    /// Review cards with an interval under `MATURE_INTERVAL_DAYS`
+    pub young: u32,
+    /// Review cards with an interval of `MATURE_INTERVAL_DAYS` or more
+    pub mature: u32,
+    /// Suspended cards, counted here instead of by type
+    pub suspended: u32,
+}
+
 /// Main collection container returned after parsing
-#[derive(Debug, uniffi::Record)]
+#[derive(Debug, Clone, uniffi::Record)]
 pub struct AnkiCollection {
     /// All decks in the collection
     pub decks: Vec<AnkiDeck>,
     /// Root-level decks only (no parent)
     pub root_decks: Vec<AnkiDeck>,
-    /// Cards grouped by deck ID (as string key for UniFFI compatibility)
+    /// Cards grouped by deck ID (as string key for UniFFI compatibility),
+    /// each deck ordered by ordinal then card ID
     pub cards_by_deck: HashMap<String, Vec<AnkiCard>>,
     /// Media store for accessing media files
     pub media: Arc<AnkiMediaStore>,
+    /// Number of cards whose note was missing from the collection
+    pub missing_note_count: u32,
+    /// Number of notes in the collection; a note can have several cards
+    pub note_count: u32,
+    /// Collection creation time and scheduler settings
+    pub meta: CollectionMeta,
+    /// Note types (empty if they couldn't be read)
+    pub note_types: Vec<AnkiNoteType>,
+    /// Tags from the `tags` table (empty on legacy collections)
+    pub tags: Vec<TagInfo>,
+    /// Summary of what happened while parsing
+    pub report: ParseReport,
 }
 
 impl AnkiCollection {
@@ -148,6 +710,8 @@ impl AnkiCollection {
         decks: Vec<AnkiDeck>,
         cards_by_deck: HashMap<i64, Vec<AnkiCard>>,
         media: Arc<AnkiMediaStore>,
+        meta: CollectionMeta,
+        note_types: Vec<AnkiNoteType>,
     ) -> Self {
         // Find root decks
         let root_decks: Vec<AnkiDeck> = decks
@@ -156,6 +720,12 @@ impl AnkiCollection {
             .cloned()
             .collect();
 
+        let missing_note_count = cards_by_deck
+            .values()
+            .flatten()
+            .filter(|c| c.note_missing)
+            .count() as u32;
+
         // Convert deck ID keys to strings for UniFFI
         let cards_by_deck_str: HashMap<String, Vec<AnkiCard>> = cards_by_deck
             .into_iter()
@@ -167,6 +737,772 @@ impl AnkiCollection {
             root_decks,
             cards_by_deck: cards_by_deck_str,
             media,
+            missing_note_count,
+            note_count: 0,
+            meta,
+            note_types,
+            tags: Vec::new(),
+            report: ParseReport::default(),
+        }
+    }
+
+    /// Cards in a deck that can be studied (not suspended or buried)
+    pub fn studyable_cards(&self, deck_id: i64) -> Vec<AnkiCard> {
+        self.cards_by_deck
+            .get(&deck_id.to_string())
+            .map(|cards| {
+                cards
+                    .iter()
+                    .filter(|c| !c.is_suspended && !c.is_buried)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Borrow every card once, ordered by deck ID then card ID
+    pub fn cards(&self) -> impl Iterator<Item = &AnkiCard> {
+        let mut decks: Vec<(i64, &Vec<AnkiCard>)> = self
+            .cards_by_deck
+            .iter()
+            .map(|(deck_id, cards)| (deck_id.parse().unwrap_or_default(), cards))
+            .collect();
+        decks.sort_by_key(|(deck_id, _)| *deck_id);
+
+        decks.into_iter().flat_map(|(_, cards)| {
+            let mut cards: Vec<&AnkiCard> = cards.iter().collect();
+            cards.sort_by_key(|c| c.id);
+            cards
+        })
+    }
+
+    /// Every card in the collection, ordered by deck ID then card ID
+    /// Clones each card; use `cards` to borrow instead
+    pub fn all_cards(&self) -> Vec<AnkiCard> {
+        self.cards().cloned().collect()
+    }
+
+    /// Media filenames referenced by cards but missing from the media store
+    /// Deduplicated and sorted. Matching follows `AnkiMediaStore::data_for`,
+    /// so references differing only in case or Unicode normalization count as present
+    pub fn missing_media(&self) -> Vec<String> {
+        let available: HashSet<String> = self
+            .media
+            .filenames()
+            .iter()
+            .map(|f| f.to_lowercase())
+            .collect();
+
+        let missing: BTreeSet<String> = self
+            .cards_by_deck
+            .values()
+            .flatten()
+            .flat_map(|card| &card.media_references)
+            .filter(|reference| !available.contains(&media_key(reference).to_lowercase()))
+            .cloned()
+            .collect();
+        missing.into_iter().collect()
+    }
+
+    /// Number of cards per note type, keyed by note type name
+    /// Cards whose note type is unknown are keyed by the numeric ID instead
+    pub fn cards_by_note_type(&self) -> HashMap<String, u32> {
+        let names: HashMap<i64, &str> = self
+            .note_types
+            .iter()
+            .map(|nt| (nt.id, nt.name.as_str()))
+            .collect();
+
+        let mut counts = HashMap::new();
+        for card in self.cards_by_deck.values().flatten() {
+            let key = match names.get(&card.note_type_id) {
+                Some(name) => name.to_string(),
+                None => card.note_type_id.to_string(),
+            };
+            *counts.entry(key).or_insert(0) += 1;
         }
+        counts
+    }
+
+    /// Total number of cards across all decks
+    pub fn total_card_count(&self) -> u32 {
+        self.cards_by_deck.values().map(|cards| cards.len()).sum::<usize>() as u32
+    }
+
+    /// New/learning/young/mature/suspended counts for a deck
+    ///
+    /// With `include_subdecks`, cards in every deck nested under it are counted too.
+    /// Review cards are mature once their interval reaches `MATURE_INTERVAL_DAYS`.
+    pub fn deck_stats(&self, deck_id: i64, include_subdecks: bool) -> DeckStats {
+        let mut stats = DeckStats::default();
+        let cards = self
+            .deck_ids(deck_id, include_subdecks)
+            .into_iter()
+            .filter_map(|id| self.cards_by_deck.get(&id.to_string()))
+            .flatten();
+        for card in cards {
+            if card.is_suspended {
+                stats.suspended += 1;
+                continue;
+            }
+            match card.card_type {
+                0 => stats.new += 1,
+                1 | 3 => stats.learning += 1,
+                _ if card.interval >= MATURE_INTERVAL_DAYS => stats.mature += 1,
+                _ => stats.young += 1,
+            }
+        }
+        stats
+    }
+
+    /// Decks holding at least one card, in `decks` order
+    ///
+    /// With `include_subdecks`, a deck also counts as non-empty when any deck
+    /// nested under it has cards.
+    pub fn non_empty_decks(&self, include_subdecks: bool) -> Vec<AnkiDeck> {
+        let has_cards = |id: &i64| {
+            self.cards_by_deck
+                .get(&id.to_string())
+                .is_some_and(|cards| !cards.is_empty())
+        };
+        self.decks
+            .iter()
+            .filter(|d| self.deck_ids(d.id, include_subdecks).iter().any(has_cards))
+            .cloned()
+            .collect()
+    }
+
+    /// A deck's ID, followed by the IDs of every deck nested under it when
+    /// `include_subdecks` is set
+    fn deck_ids(&self, deck_id: i64, include_subdecks: bool) -> Vec<i64> {
+        let mut deck_ids = vec![deck_id];
+        if include_subdecks {
+            if let Some(deck) = self.decks.iter().find(|d| d.id == deck_id) {
+                let prefix = format!("{}::", deck.name);
+                deck_ids.extend(
+                    self.decks
+                        .iter()
+                        .filter(|d| d.name.starts_with(&prefix))
+                        .map(|d| d.id),
+                );
+            }
+        }
+        deck_ids
+    }
+
+    /// New cards in a deck that can be studied (not suspended or buried)
+    ///
+    /// With `include_subdecks`, new cards in every deck nested under it follow
+    /// the deck's own.
+    pub fn new_cards(&self, deck_id: i64, include_subdecks: bool) -> Vec<AnkiCard> {
+        self.deck_ids(deck_id, include_subdecks)
+            .into_iter()
+            .filter_map(|id| self.cards_by_deck.get(&id.to_string()))
+            .flatten()
+            .filter(|c| c.is_new && !c.is_suspended && !c.is_buried)
+            .cloned()
+            .collect()
+    }
+
+    /// Cards keyed by root deck ID, with every subdeck's cards merged in
+    ///
+    /// Parentage comes from deck name prefixes: a deck belongs to its highest
+    /// ancestor that exists in `decks`. Placeholder "Deck {id}" decks and
+    /// cards in unknown decks stay under their own ID. Within a root, cards
+    /// are grouped by deck name, so the root's own cards come first.
+    pub fn flattened_by_root(&self) -> HashMap<i64, Vec<AnkiCard>> {
+        let is_placeholder = |deck: &AnkiDeck| deck.name == format!("Deck {}", deck.id);
+        let ids_by_name: HashMap<&str, i64> = self
+            .decks
+            .iter()
+            .filter(|d| !is_placeholder(d))
+            .map(|d| (d.name.as_str(), d.id))
+            .collect();
+
+        let mut decks: Vec<&AnkiDeck> = self.decks.iter().collect();
+        decks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut flattened: HashMap<i64, Vec<AnkiCard>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for deck in decks {
+            let root_id = if is_placeholder(deck) {
+                deck.id
+            } else {
+                deck.name
+                    .match_indices("::")
+                    .map(|(i, _)| &deck.name[..i])
+                    .find_map(|ancestor| ids_by_name.get(ancestor).copied())
+                    .unwrap_or(deck.id)
+            };
+            if let Some(cards) = self.cards_by_deck.get(&deck.id.to_string()) {
+                flattened.entry(root_id).or_default().extend(cards.iter().cloned());
+            }
+            seen.insert(deck.id.to_string());
+        }
+
+        for (deck_id, cards) in &self.cards_by_deck {
+            if !seen.contains(deck_id) {
+                if let Ok(id) = deck_id.parse() {
+                    flattened.entry(id).or_default().extend(cards.iter().cloned());
+                }
+            }
+        }
+        flattened
+    }
+
+    /// Tag hierarchy built from card tags and the `tags` table
+    ///
+    /// Tags nest on "::" like deck names; intermediate tags that no card
+    /// carries directly still get a node. Tags are matched case-insensitively,
+    /// preferring the spelling in the `tags` table, then the first one seen.
+    pub fn tag_tree(&self) -> Vec<TagNode> {
+        // Lowercased full name -> (display name, card IDs)
+        let mut nodes: HashMap<String, (String, HashSet<i64>)> = HashMap::new();
+        let mut add = |name: &str, card_id: Option<i64>| {
+            let mut path = Some(name);
+            while let Some(current) = path {
+                let (_, card_ids) = nodes
+                    .entry(current.to_lowercase())
+                    .or_insert_with(|| (current.to_string(), HashSet::new()));
+                card_ids.extend(card_id);
+                path = parent_name(current);
+            }
+        };
+
+        for tag in &self.tags {
+            add(&tag.name, None);
+        }
+        for card in self.cards_by_deck.values().flatten() {
+            for tag in &card.tags {
+                add(tag, Some(card.id));
+            }
+        }
+
+        let expanded: HashMap<String, bool> = self
+            .tags
+            .iter()
+            .map(|tag| (tag.name.to_lowercase(), tag.expanded))
+            .collect();
+
+        let mut children: HashMap<Option<String>, Vec<String>> = HashMap::new();
+        for key in nodes.keys() {
+            children
+                .entry(parent_name(key).map(str::to_string))
+                .or_default()
+                .push(key.clone());
+        }
+
+        fn build(
+            parent: Option<String>,
+            nodes: &HashMap<String, (String, HashSet<i64>)>,
+            children: &mut HashMap<Option<String>, Vec<String>>,
+            expanded: &HashMap<String, bool>,
+        ) -> Vec<TagNode> {
+            let mut keys = children.remove(&parent).unwrap_or_default();
+            keys.sort();
+            keys.into_iter()
+                .map(|key| {
+                    let (name, card_ids) = &nodes[&key];
+                    TagNode {
+                        name: name.clone(),
+                        short_name: leaf_name(name).to_string(),
+                        card_count: card_ids.len() as u32,
+                        expanded: expanded.get(&key).copied().unwrap_or(true),
+                        children: build(Some(key), nodes, children, expanded),
+                    }
+                })
+                .collect()
+        }
+
+        build(None, &nodes, &mut children, &expanded)
+    }
+}
+
+/// A parsed collection held on the Rust side for queries
+///
+/// Records are copied across the FFI boundary on every call, so hand the
+/// collection over once and run queries against the handle instead.
+#[derive(Debug, uniffi::Object)]
+pub struct CollectionHandle {
+    collection: AnkiCollection,
+    /// Card ID → (deck key, position) lookup for `card`, built on first use
+    card_locations: OnceLock<HashMap<i64, (String, usize)>>,
+}
+
+#[uniffi::export]
+impl CollectionHandle {
+    #[uniffi::constructor]
+    pub fn new(collection: AnkiCollection) -> Arc<Self> {
+        Arc::new(Self {
+            collection,
+            card_locations: OnceLock::new(),
+        })
+    }
+
+    /// Look up a card by ID
+    ///
+    /// The ID index is built on the first call and reused afterwards. If two
+    /// cards share an ID, the one in the lowest deck ID wins, then the first
+    /// in that deck.
+    pub fn card(&self, id: i64) -> Option<AnkiCard> {
+        let cards_by_deck = &self.collection.cards_by_deck;
+        let locations = self.card_locations.get_or_init(|| {
+            let mut decks: Vec<(&String, &Vec<AnkiCard>)> = cards_by_deck.iter().collect();
+            decks.sort_by_key(|(deck_id, _)| deck_id.parse::<i64>().unwrap_or_default());
+
+            let mut locations = HashMap::new();
+            for (deck_id, cards) in decks {
+                for (position, card) in cards.iter().enumerate() {
+                    locations.entry(card.id).or_insert_with(|| (deck_id.clone(), position));
+                }
+            }
+            locations
+        });
+
+        let (deck_id, position) = locations.get(&id)?;
+        cards_by_deck.get(deck_id)?.get(*position).cloned()
+    }
+
+    /// See `AnkiCollection::all_cards`
+    pub fn all_cards(&self) -> Vec<AnkiCard> {
+        self.collection.all_cards()
+    }
+
+    /// See `AnkiCollection::total_card_count`
+    pub fn total_card_count(&self) -> u32 {
+        self.collection.total_card_count()
+    }
+
+    /// See `AnkiCollection::studyable_cards`
+    pub fn studyable_cards(&self, deck_id: i64) -> Vec<AnkiCard> {
+        self.collection.studyable_cards(deck_id)
+    }
+
+    /// See `AnkiCollection::missing_media`
+    pub fn missing_media(&self) -> Vec<String> {
+        self.collection.missing_media()
+    }
+
+    /// See `AnkiCollection::cards_by_note_type`
+    pub fn cards_by_note_type(&self) -> HashMap<String, u32> {
+        self.collection.cards_by_note_type()
+    }
+
+    /// See `AnkiCollection::deck_stats`
+    pub fn deck_stats(&self, deck_id: i64, include_subdecks: bool) -> DeckStats {
+        self.collection.deck_stats(deck_id, include_subdecks)
+    }
+
+    /// See `AnkiCollection::non_empty_decks`
+    pub fn non_empty_decks(&self, include_subdecks: bool) -> Vec<AnkiDeck> {
+        self.collection.non_empty_decks(include_subdecks)
+    }
+
+    /// See `AnkiCollection::new_cards`
+    pub fn new_cards(&self, deck_id: i64, include_subdecks: bool) -> Vec<AnkiCard> {
+        self.collection.new_cards(deck_id, include_subdecks)
+    }
+
+    /// See `AnkiCollection::flattened_by_root`
+    pub fn flattened_by_root(&self) -> HashMap<i64, Vec<AnkiCard>> {
+        self.collection.flattened_by_root()
+    }
+
+    /// See `AnkiCollection::tag_tree`
+    pub fn tag_tree(&self) -> Vec<TagNode> {
+        self.collection.tag_tree()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Collection with an empty media store, default meta and no note types
+    pub(crate) fn collection(
+        decks: Vec<AnkiDeck>,
+        cards_by_deck: HashMap<i64, Vec<AnkiCard>>,
+    ) -> AnkiCollection {
+        AnkiCollection::new(
+            decks,
+            cards_by_deck,
+            Arc::new(AnkiMediaStore::new()),
+            CollectionMeta::default(),
+            Vec::new(),
+        )
+    }
+
+    fn card(id: i64, deck_id: i64) -> AnkiCard {
+        AnkiCard {
+            id,
+            deck_id,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_all_cards_order() {
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(20, vec![card(5, 20), card(1, 20)]);
+        cards_by_deck.insert(3, vec![card(9, 3)]);
+        let collection = collection(Vec::new(), cards_by_deck);
+
+        let ids: Vec<i64> = collection.all_cards().iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![9, 1, 5]);
+        assert_eq!(collection.total_card_count(), 3);
+    }
+
+    #[test]
+    fn test_cards_by_note_type() {
+        let basic = AnkiNoteType {
+            id: 1,
+            name: "Basic".to_string(),
+            field_names: Vec::new(),
+            templates: Vec::new(),
+            css: String::new(),
+            is_cloze: false,
+        };
+        let typed = |id, note_type_id| AnkiCard { note_type_id, ..card(id, 1) };
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(1, vec![typed(1, 1), typed(2, 1), typed(3, 7)]);
+        let mut collection = collection(Vec::new(), cards_by_deck);
+        collection.note_types = vec![basic];
+
+        let counts = collection.cards_by_note_type();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["Basic"], 2);
+        assert_eq!(counts["7"], 1);
+    }
+
+    #[test]
+    fn test_card_lookup() {
+        let mut duplicate = card(1, 2);
+        duplicate.note_id = 99;
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(1, vec![card(1, 1), card(2, 1)]);
+        cards_by_deck.insert(2, vec![duplicate, card(3, 2)]);
+        let handle = CollectionHandle::new(collection(Vec::new(), cards_by_deck));
+
+        assert_eq!(handle.card(3).unwrap().deck_id, 2);
+        // The duplicate ID resolves to the card in the lower deck
+        let first = handle.card(1).unwrap();
+        assert_eq!((first.deck_id, first.note_id), (1, 0));
+        assert!(handle.card(42).is_none());
+    }
+
+    #[test]
+    fn test_flattened_by_root() {
+        let collection = collection(
+            vec![
+                AnkiDeck::from_name(1, "Japanese".to_string()),
+                AnkiDeck::from_name(2, "Japanese::Kanji".to_string()),
+                AnkiDeck::from_name(3, "Japanese::Kanji::N5".to_string()),
+                // Parent "Korean" doesn't exist, so this is its own root
+                AnkiDeck::from_name(4, "Korean::Verbs".to_string()),
+                AnkiDeck::from_name(5, "Deck 5".to_string()),
+            ],
+            HashMap::from([
+                (1, vec![card(10, 1)]),
+                (2, vec![card(20, 2)]),
+                (3, vec![card(30, 3), card(31, 3)]),
+                (4, vec![card(40, 4)]),
+                (5, vec![card(50, 5)]),
+                (6, vec![card(60, 6)]),
+            ]),
+        );
+
+        let flattened = collection.flattened_by_root();
+        let ids = |deck_id: i64| -> Vec<i64> { flattened[&deck_id].iter().map(|c| c.id).collect() };
+        assert_eq!(flattened.len(), 4);
+        assert_eq!(ids(1), vec![10, 20, 30, 31]);
+        assert_eq!(ids(4), vec![40]);
+        assert_eq!(ids(5), vec![50]);
+        assert_eq!(ids(6), vec![60]);
+        // The collection itself is untouched
+        assert_eq!(collection.cards_by_deck["3"].len(), 2);
+    }
+
+    #[test]
+    fn test_non_empty_decks() {
+        let collection = collection(
+            vec![
+                AnkiDeck::from_name(1, "Japanese".to_string()),
+                AnkiDeck::from_name(2, "Japanese::Kanji".to_string()),
+                AnkiDeck::from_name(3, "Empty".to_string()),
+                AnkiDeck::from_name(4, "Deck 4".to_string()),
+            ],
+            HashMap::from([(2, vec![card(20, 2)]), (3, Vec::new()), (4, vec![card(40, 4)])]),
+        );
+
+        let ids = |decks: Vec<AnkiDeck>| -> Vec<i64> { decks.iter().map(|d| d.id).collect() };
+        assert_eq!(ids(collection.non_empty_decks(false)), vec![2, 4]);
+        assert_eq!(ids(collection.non_empty_decks(true)), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn test_tag_tree() {
+        let tagged = |id: i64, tags: &[&str]| AnkiCard {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            ..card(id, 1)
+        };
+        let mut collection = collection(
+            vec![AnkiDeck::from_name(1, "Deck".to_string())],
+            HashMap::from([(1, vec![
+                tagged(1, &["Lang::Japanese::Verbs", "lang::japanese"]),
+                tagged(2, &["lang::Korean"]),
+                tagged(3, &["verbs"]),
+            ])]),
+        );
+        collection.tags = vec![
+            TagInfo { name: "Lang".to_string(), expanded: false },
+            TagInfo { name: "unused".to_string(), expanded: true },
+        ];
+
+        let tree = collection.tag_tree();
+        let names: Vec<&str> = tree.iter().map(|n| n.name.as_str()).collect();
+        assert_eq!(names, vec!["Lang", "unused", "verbs"]);
+
+        let lang = &tree[0];
+        assert_eq!(lang.card_count, 2);
+        assert!(!lang.expanded);
+        assert_eq!(lang.children.len(), 2);
+
+        // Intermediate node created from the first spelling seen
+        let japanese = &lang.children[0];
+        assert_eq!(japanese.name, "Lang::Japanese");
+        assert_eq!(japanese.short_name, "Japanese");
+        assert_eq!(japanese.card_count, 1);
+        assert!(japanese.expanded);
+        assert_eq!(japanese.children[0].short_name, "Verbs");
+        assert_eq!(lang.children[1].name, "lang::Korean");
+
+        assert_eq!(tree[1].card_count, 0);
+        assert!(tree[1].children.is_empty());
+    }
+
+    #[test]
+    fn test_deck_stats() {
+        let scheduled = |id, deck_id, card_type, interval| AnkiCard {
+            card_type,
+            interval,
+            ..card(id, deck_id)
+        };
+        let mut suspended = scheduled(6, 1, 2, 30);
+        suspended.is_suspended = true;
+
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(
+            1,
+            vec![
+                scheduled(1, 1, 0, 0),
+                scheduled(2, 1, 1, -600),
+                scheduled(3, 1, 2, 20),
+                scheduled(4, 1, 2, 21),
+                scheduled(5, 1, 3, 1),
+                suspended,
+            ],
+        );
+        cards_by_deck.insert(2, vec![scheduled(7, 2, 0, 0)]);
+        cards_by_deck.insert(3, vec![scheduled(8, 3, 0, 0)]);
+        let decks = vec![
+            AnkiDeck::from_name(1, "A".to_string()),
+            AnkiDeck::from_name(2, "A::B".to_string()),
+            AnkiDeck::from_name(3, "AB".to_string()),
+        ];
+        let collection = collection(decks, cards_by_deck);
+
+        let expected = DeckStats { new: 1, learning: 2, young: 1, mature: 1, suspended: 1 };
+        assert_eq!(collection.deck_stats(1, false), expected);
+        assert_eq!(collection.deck_stats(1, true), DeckStats { new: 2, ..expected });
+    }
+
+    #[test]
+    fn test_new_cards() {
+        let new = |id, deck_id| AnkiCard { is_new: true, ..card(id, deck_id) };
+        let collection = collection(
+            vec![
+                AnkiDeck::from_name(1, "A".to_string()),
+                AnkiDeck::from_name(2, "A::B".to_string()),
+            ],
+            HashMap::from([
+                (1, vec![new(1, 1), card(2, 1), AnkiCard { is_suspended: true, ..new(3, 1) }]),
+                (2, vec![new(4, 2)]),
+            ]),
+        );
+
+        let ids = |cards: Vec<AnkiCard>| -> Vec<i64> { cards.iter().map(|c| c.id).collect() };
+        assert_eq!(ids(collection.new_cards(1, false)), vec![1]);
+        assert_eq!(ids(collection.new_cards(1, true)), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_missing_media() {
+        let media = Arc::new(AnkiMediaStore::new());
+        media.insert("a.mp3".to_string(), Vec::new());
+        media.add_filename("B.png".to_string());
+
+        let with_refs = |id, refs: &[&str]| AnkiCard {
+            media_references: refs.iter().map(|r| r.to_string()).collect(),
+            ..card(id, 1)
+        };
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(
+            1,
+            vec![
+                with_refs(1, &["a.mp3", "missing.png"]),
+                with_refs(2, &["b.png", "missing.png", "gone.mp3"]),
+            ],
+        );
+        let mut collection = collection(Vec::new(), cards_by_deck);
+        collection.media = media;
+
+        assert_eq!(collection.missing_media(), vec!["gone.mp3", "missing.png"]);
+    }
+
+    #[test]
+    fn test_media_bytes_by_type() {
+        let store = AnkiMediaStore::new();
+        store.insert("a.mp3".to_string(), vec![0; 10]);
+        store.insert("b.MP3".to_string(), vec![0; 5]);
+        store.insert("c.png".to_string(), vec![0; 3]);
+        store.insert("d.mp4".to_string(), vec![0; 7]);
+
+        assert_eq!(store.total_bytes(), 25);
+        let by_type = store.bytes_by_type();
+        assert_eq!(by_type["audio"], 15);
+        assert_eq!(by_type["image"], 3);
+        assert_eq!(by_type["video"], 7);
+        assert!(!by_type.contains_key("unknown"));
+    }
+
+    #[test]
+    fn test_media_remove_and_clear() {
+        let store = AnkiMediaStore::new();
+        store.insert("a.mp3".to_string(), b"audio".to_vec());
+        store.insert("b.png".to_string(), b"image".to_vec());
+        store.insert("c.png".to_string(), b"image".to_vec());
+
+        assert_eq!(store.remove("a.mp3".to_string()), Some(b"audio".to_vec()));
+        assert_eq!(store.remove("a.mp3".to_string()), None);
+        assert_eq!(store.filenames(), vec!["b.png", "c.png"]);
+
+        store.clear_data();
+        assert_eq!(store.count(), 2);
+        assert_eq!(store.data_for("b.png".to_string()), None);
+        assert_eq!(store.total_bytes(), 0);
+    }
+
+    #[test]
+    fn test_media_unicode_normalization() {
+        let store = AnkiMediaStore::new();
+        // NFC: precomposed é
+        store.insert("caf\u{e9}.mp3".to_string(), b"audio".to_vec());
+
+        // NFD: e + combining acute accent
+        let nfd = "cafe\u{301}.mp3".to_string();
+        assert_eq!(store.data_for(nfd.clone()), Some(b"audio".to_vec()));
+
+        // Same file either way, so no duplicate entry
+        store.insert(nfd, b"new".to_vec());
+        assert_eq!(store.count(), 1);
+    }
+
+    #[test]
+    fn test_media_case_insensitive_lookup() {
+        let store = AnkiMediaStore::new();
+        store.insert("image.jpg".to_string(), b"lower".to_vec());
+        store.insert("Photo.PNG".to_string(), b"photo".to_vec());
+
+        assert_eq!(store.data_for("Image.JPG".to_string()), Some(b"lower".to_vec()));
+        assert_eq!(store.data_for("photo.png".to_string()), Some(b"photo".to_vec()));
+        assert_eq!(store.data_for("other.png".to_string()), None);
+
+        // An exact match wins when two files differ only by case
+        store.insert("IMAGE.jpg".to_string(), b"upper".to_vec());
+        assert_eq!(store.data_for("IMAGE.jpg".to_string()), Some(b"upper".to_vec()));
+        assert_eq!(store.data_for("image.jpg".to_string()), Some(b"lower".to_vec()));
+    }
+
+    #[test]
+    fn test_media_image_dimensions() {
+        let store = AnkiMediaStore::new();
+        let gif = |width: u8| vec![b'G', b'I', b'F', b'8', b'9', b'a', width, 0, 10, 0];
+        store.insert("a.gif".to_string(), gif(20));
+        store.insert("a.mp3".to_string(), b"ID3audio".to_vec());
+
+        let size = |width, height| Some(ImageDimensions { width, height });
+        assert_eq!(store.image_dimensions("a.gif".to_string()), size(20, 10));
+        assert_eq!(store.image_dimensions("a.mp3".to_string()), None);
+        assert_eq!(store.image_dimensions("missing.png".to_string()), None);
+
+        // Replacing the data invalidates the cached size
+        store.insert("a.gif".to_string(), gif(40));
+        assert_eq!(store.image_dimensions("a.gif".to_string()), size(40, 10));
+        assert_eq!(store.audio_duration_ms("a.gif".to_string()), None);
+    }
+
+    #[test]
+    fn test_media_resolve() {
+        let store = AnkiMediaStore::new();
+        store.insert("Photo Café.JPG".to_string(), b"image".to_vec());
+        store.add_alias("7".to_string(), "Photo Café.JPG".to_string());
+
+        let resolve = |reference: &str| store.resolve(reference.to_string());
+        assert_eq!(resolve("Photo Café.JPG"), Some(b"image".to_vec()));
+        // Decomposed "é" and different case
+        assert_eq!(resolve("photo cafe\u{301}.jpg"), Some(b"image".to_vec()));
+        assert_eq!(resolve("Photo%20Caf%C3%A9.JPG"), Some(b"image".to_vec()));
+        assert_eq!(resolve("7"), Some(b"image".to_vec()));
+        assert_eq!(resolve("8"), None);
+    }
+
+    #[test]
+    fn test_media_fingerprints() {
+        let store = AnkiMediaStore::new();
+        store.insert("a.mp3".to_string(), b"audio".to_vec());
+        store.insert("b.png".to_string(), b"image".to_vec());
+
+        let before = store.fingerprint("a.mp3".to_string()).unwrap();
+        assert_eq!(before.len(), 16);
+        assert_eq!(store.fingerprint("a.mp3".to_string()).unwrap(), before);
+        assert_eq!(store.fingerprint("missing.png".to_string()), None);
+
+        // Replacing the data invalidates the cached hash
+        store.insert("a.mp3".to_string(), b"new audio".to_vec());
+        let fingerprints = store.all_fingerprints();
+        assert_eq!(fingerprints.len(), 2);
+        assert_ne!(fingerprints["a.mp3"], before);
+    }
+
+    #[test]
+    fn test_studyable_cards() {
+        let mut cards_by_deck = HashMap::new();
+        cards_by_deck.insert(
+            1,
+            vec![
+                card(1, 1),
+                AnkiCard { is_suspended: true, ..card(2, 1) },
+                AnkiCard { is_buried: true, ..card(3, 1) },
+            ],
+        );
+        let collection = collection(Vec::new(), cards_by_deck);
+
+        let ids: Vec<i64> = collection.studyable_cards(1).iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1]);
+        assert!(collection.studyable_cards(2).is_empty());
+    }
+
+    #[test]
+    fn test_collection_handle() {
+        let handle = CollectionHandle::new(collection(
+            vec![AnkiDeck::from_name(1, "A".to_string())],
+            HashMap::from([(1, vec![card(2, 1), AnkiCard { is_new: true, ..card(1, 1) }])]),
+        ));
+
+        assert_eq!(handle.total_card_count(), 2);
+        let ids: Vec<i64> = handle.all_cards().iter().map(|c| c.id).collect();
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(handle.new_cards(1, false).len(), 1);
+        assert_eq!(handle.non_empty_decks(false).len(), 1);
     }
 }
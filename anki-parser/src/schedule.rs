@@ -0,0 +1,123 @@
+use crate::models::{AnkiCard, CollectionMeta};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+/// `cards.due` values above this are epoch seconds rather than day numbers,
+/// the same threshold Anki uses
+const DUE_TIMESTAMP_THRESHOLD: i64 = 1_000_000_000;
+
+/// Anki's day number for a moment: whole study days since the collection was created
+///
+/// Follows Anki's scheduler timing: V1 counts 24-hour periods from `created_at`;
+/// V2+ counts local days that start at `rollover_hour`. The local timezone is
+/// taken to be the one the collection was created in (`creation_offset`), or UTC
+/// for older collections that didn't record it.
+pub fn day_number(meta: &CollectionMeta, epoch_secs: i64) -> i64 {
+    if meta.scheduler_version == 1 {
+        return (epoch_secs - meta.created_at).div_euclid(SECONDS_PER_DAY);
+    }
+
+    let offset_secs = i64::from(meta.creation_offset.unwrap_or(0)) * 60;
+    let rollover_secs = i64::from(meta.rollover_hour) * 3600;
+    let created_local = meta.created_at - offset_secs;
+    let now_local = epoch_secs - offset_secs;
+
+    match meta.creation_offset {
+        // Calendar days between the creation and current dates, where the
+        // current day only counts once its rollover hour has passed
+        Some(_) => {
+            let days = now_local.div_euclid(SECONDS_PER_DAY)
+                - created_local.div_euclid(SECONDS_PER_DAY);
+            if now_local.rem_euclid(SECONDS_PER_DAY) >= rollover_secs {
+                days
+            } else {
+                days - 1
+            }
+        }
+        // Legacy V2 timing: days since the rollover hour on the creation date
+        None => {
+            let created_at_rollover =
+                created_local.div_euclid(SECONDS_PER_DAY) * SECONDS_PER_DAY + rollover_secs;
+            (now_local - created_at_rollover).div_euclid(SECONDS_PER_DAY)
+        }
+    }
+}
+
+/// Days since the card was last reviewed, as FSRS's `days_elapsed` expects
+///
+/// New cards are 0. Review cards use their due day minus their interval, as
+/// Anki does without a review log; (re)learning cards use the day they were
+/// last modified. Day boundaries follow `day_number`.
+pub fn days_elapsed_for_card(card: &AnkiCard, meta: &CollectionMeta, now_epoch_ms: i64) -> u32 {
+    let today = day_number(meta, now_epoch_ms.div_euclid(1000));
+    let last_review_day = match card.card_type {
+        0 => return 0,
+        2 if card.due < DUE_TIMESTAMP_THRESHOLD => card.due - i64::from(card.interval),
+        _ => day_number(meta, card.card_modified),
+    };
+    (today - last_review_day).max(0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-01-01 04:00 in Tokyo (UTC+9)
+    const CREATED: i64 = 1_704_049_200;
+    /// 2024-01-11 03:59 in Tokyo, a minute before the day rolls over
+    const BEFORE_ROLLOVER: i64 = 1_704_913_140;
+
+    fn tokyo() -> CollectionMeta {
+        CollectionMeta {
+            created_at: CREATED,
+            scheduler_version: 2,
+            rollover_hour: 4,
+            creation_offset: Some(-540),
+        }
+    }
+
+    #[test]
+    fn test_day_number_rollover() {
+        let meta = tokyo();
+        assert_eq!(day_number(&meta, CREATED), 0);
+        assert_eq!(day_number(&meta, BEFORE_ROLLOVER), 9);
+        assert_eq!(day_number(&meta, BEFORE_ROLLOVER + 60), 10);
+    }
+
+    #[test]
+    fn test_day_number_legacy_timing() {
+        // 2024-01-01 00:00 UTC, with no creation offset recorded
+        let meta = CollectionMeta { created_at: 1_704_067_200, ..tokyo() };
+        let two_days_later = meta.created_at + 2 * SECONDS_PER_DAY;
+        let legacy_v2 = CollectionMeta { creation_offset: None, ..meta.clone() };
+        assert_eq!(day_number(&legacy_v2, two_days_later + 4 * 3600 - 60), 1);
+        assert_eq!(day_number(&legacy_v2, two_days_later + 4 * 3600), 2);
+
+        let v1 = CollectionMeta { scheduler_version: 1, ..legacy_v2 };
+        assert_eq!(day_number(&v1, two_days_later - 1), 1);
+        assert_eq!(day_number(&v1, two_days_later), 2);
+    }
+
+    #[test]
+    fn test_days_elapsed_for_card() {
+        let meta = tokyo();
+        let now_ms = (BEFORE_ROLLOVER + 60) * 1000;
+
+        let new = AnkiCard { card_type: 0, ..Default::default() };
+        assert_eq!(days_elapsed_for_card(&new, &meta, now_ms), 0);
+
+        // Due on day 20 with a 15-day interval: last reviewed on day 5
+        let review = AnkiCard { card_type: 2, due: 20, interval: 15, ..Default::default() };
+        assert_eq!(days_elapsed_for_card(&review, &meta, now_ms), 5);
+        assert_eq!(days_elapsed_for_card(&review, &meta, now_ms - 60_000), 4);
+
+        // Learning card last touched 2024-01-09 12:00 in Tokyo (day 8)
+        let learning = AnkiCard {
+            card_type: 1,
+            due: 1_704_800_000,
+            card_modified: 1_704_769_200,
+            ..Default::default()
+        };
+        assert_eq!(days_elapsed_for_card(&learning, &meta, now_ms), 2);
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use crate::models::AnkiNoteType;
+
+/// Render the question and answer sides of a card from its note type's template
+///
+/// Returns `None` if the note type has no template for the given ordinal.
+pub fn render_card(
+    note_type: &AnkiNoteType,
+    ordinal: u32,
+    fields: &[String],
+) -> Option<(String, String)> {
+    let template = note_type.template(ordinal)?;
+    Some(render_templates(
+        &template.question_format,
+        &template.answer_format,
+        &note_type.field_names,
+        fields,
+    ))
+}
+
+/// Render question/answer HTML from template strings and positional fields
+///
+/// Supports:
+/// - `{{Field}}` substitution by field name
+/// - `{{FrontSide}}` on the answer side (the rendered question)
+/// - `{{#Field}}...{{/Field}}` sections, shown when the field is non-empty
+/// - `{{^Field}}...{{/Field}}` sections, shown when the field is empty
+/// - Filters such as `{{text:Field}}` are reduced to the field value;
+///   `{{type:Field}}` renders nothing
+pub fn render_templates(
+    question_format: &str,
+    answer_format: &str,
+    field_names: &[String],
+    fields: &[String],
+) -> (String, String) {
+    let mut values: HashMap<&str, &str> = field_names
+        .iter()
+        .zip(fields.iter())
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect();
+
+    let question = render(question_format, &values);
+
+    values.insert("FrontSide", &question);
+    let answer = render(answer_format, &values);
+
+    (question, answer)
+}
+
+/// Render a single template against field values
+fn render(template: &str, values: &HashMap<&str, &str>) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            // Unterminated tag, emit as-is
+            out.push_str(&rest[start..]);
+            return out;
+        };
+
+        let tag = after[..end].trim();
+        rest = &after[end + 2..];
+
+        if let Some(name) = tag.strip_prefix('#').or_else(|| tag.strip_prefix('^')) {
+            let name = name.trim();
+            let inverted = tag.starts_with('^');
+
+            // Find the matching close tag; an unclosed section runs to the end
+            let close = format!("{{{{/{}}}}}", name);
+            let (inner, remainder) = match rest.find(&close) {
+                Some(pos) => (&rest[..pos], &rest[pos + close.len()..]),
+                None => (rest, ""),
+            };
+
+            let non_empty = values
+                .get(name)
+                .is_some_and(|value| !value.trim().is_empty());
+
+            if non_empty != inverted {
+                out.push_str(&render(inner, values));
+            }
+
+            rest = remainder;
+        } else if tag.starts_with('/') {
+            // Stray close tag without a matching open, drop it
+        } else {
+            out.push_str(&substitute(tag, values));
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// Resolve a field reference, applying (or ignoring) any filters
+fn substitute(tag: &str, values: &HashMap<&str, &str>) -> String {
+    let mut parts: Vec<&str> = tag.split(':').collect();
+    let name = parts.pop().unwrap_or("").trim();
+
+    // Type-in answer boxes have no text representation
+    if parts.iter().any(|filter| filter.trim() == "type") {
+        return String::new();
+    }
+
+    values.get(name).map(|v| v.to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::AnkiCardTemplate;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_basic_substitution() {
+        let (q, a) = render_templates(
+            "{{Front}}",
+            "{{FrontSide}}<hr id=answer>{{Back}}",
+            &names(&["Front", "Back"]),
+            &names(&["hello", "world"]),
+        );
+        assert_eq!(q, "hello");
+        assert_eq!(a, "hello<hr id=answer>world");
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let template = "{{Front}}{{#Extra}}<br>{{Extra}}{{/Extra}}{{^Extra}} (none){{/Extra}}";
+        let field_names = names(&["Front", "Extra"]);
+
+        let (q, _) = render_templates(template, "", &field_names, &names(&["a", "note"]));
+        assert_eq!(q, "a<br>note");
+
+        let (q, _) = render_templates(template, "", &field_names, &names(&["a", " "]));
+        assert_eq!(q, "a (none)");
+    }
+
+    #[test]
+    fn test_filters_and_unknown_fields() {
+        let field_names = names(&["Front", "Back"]);
+        let fields = names(&["a", "b"]);
+
+        let (q, a) = render_templates("{{text:Front}}{{Missing}}", "{{type:Back}}", &field_names, &fields);
+        assert_eq!(q, "a");
+        assert_eq!(a, "");
+    }
+
+    #[test]
+    fn test_render_card_by_ordinal() {
+        let note_type = AnkiNoteType {
+            id: 1,
+            name: "Basic (and reversed card)".to_string(),
+            field_names: names(&["Front", "Back"]),
+            templates: vec![
+                AnkiCardTemplate {
+                    name: "Card 1".to_string(),
+                    ordinal: 0,
+                    question_format: "{{Front}}".to_string(),
+                    answer_format: "{{Back}}".to_string(),
+                },
+                AnkiCardTemplate {
+                    name: "Card 2".to_string(),
+                    ordinal: 1,
+                    question_format: "{{Back}}".to_string(),
+                    answer_format: "{{Front}}".to_string(),
+                },
+            ],
+            css: String::new(),
+            is_cloze: false,
+        };
+        let fields = names(&["a", "b"]);
+
+        assert_eq!(render_card(&note_type, 1, &fields), Some(("b".to_string(), "a".to_string())));
+        assert_eq!(render_card(&note_type, 2, &fields), None);
+    }
+}
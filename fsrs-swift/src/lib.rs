@@ -2,7 +2,10 @@
 //!
 //! Wraps the fsrs-rs crate for use in Swift via UniFFI.
 
-use fsrs::{FSRS, MemoryState as InternalMemoryState, NextStates as InternalNextStates};
+use fsrs::{
+    FSRSItem, FSRSReview, SimulatorConfig, DEFAULT_PARAMETERS, FSRS,
+    MemoryState as InternalMemoryState, NextStates as InternalNextStates,
+};
 
 uniffi::setup_scaffolding!();
 
@@ -42,6 +45,24 @@ impl From<MemoryState> for InternalMemoryState {
     }
 }
 
+/// A single past review, used to rebuild a card's memory state
+#[derive(Debug, Clone, Copy, uniffi::Record)]
+pub struct FsrsReview {
+    /// Rating given at this review
+    pub rating: Rating,
+    /// Days since the previous review (must be 0 for the first review)
+    pub delta_t: u32,
+}
+
+impl From<FsrsReview> for FSRSReview {
+    fn from(r: FsrsReview) -> Self {
+        Self {
+            rating: r.rating as u32,
+            delta_t: r.delta_t,
+        }
+    }
+}
+
 /// Scheduling information for a single rating option
 #[derive(Debug, Clone, Copy, uniffi::Record)]
 pub struct SchedulingInfo {
@@ -49,6 +70,26 @@ pub struct SchedulingInfo {
     pub memory: MemoryState,
     /// Days until next review (rounded from float)
     pub interval: u32,
+    /// Predicted recall probability when the card comes due
+    pub retrievability_at_due: f32,
+    /// Interval in days as computed by FSRS, before rounding, fuzz or capping
+    pub raw_interval_days: f32,
+}
+
+impl SchedulingInfo {
+    fn new(memory: MemoryState, interval: u32, raw_interval_days: f32) -> Self {
+        Self {
+            memory,
+            interval,
+            retrievability_at_due: current_retrievability(memory.stability, interval),
+            raw_interval_days,
+        }
+    }
+
+    /// Replace the interval, keeping `retrievability_at_due` in step
+    fn set_interval(&mut self, interval: u32) {
+        *self = Self::new(self.memory, interval, self.raw_interval_days);
+    }
 }
 
 /// All possible next states for each rating option
@@ -63,27 +104,169 @@ pub struct NextStates {
 impl From<InternalNextStates> for NextStates {
     fn from(ns: InternalNextStates) -> Self {
         Self {
-            again: SchedulingInfo {
-                memory: ns.again.memory.into(),
-                // Round to nearest day, minimum 0 (same day)
-                interval: ns.again.interval.round() as u32,
-            },
-            hard: SchedulingInfo {
-                memory: ns.hard.memory.into(),
-                interval: ns.hard.interval.round().max(1.0) as u32,
-            },
-            good: SchedulingInfo {
-                memory: ns.good.memory.into(),
-                interval: ns.good.interval.round().max(1.0) as u32,
-            },
-            easy: SchedulingInfo {
-                memory: ns.easy.memory.into(),
-                interval: ns.easy.interval.round().max(1.0) as u32,
-            },
+            // Round to nearest day, minimum 0 (same day)
+            again: SchedulingInfo::new(
+                ns.again.memory.into(),
+                ns.again.interval.round() as u32,
+                ns.again.interval,
+            ),
+            hard: SchedulingInfo::new(
+                ns.hard.memory.into(),
+                ns.hard.interval.round().max(1.0) as u32,
+                ns.hard.interval,
+            ),
+            good: SchedulingInfo::new(
+                ns.good.memory.into(),
+                ns.good.interval.round().max(1.0) as u32,
+                ns.good.interval,
+            ),
+            easy: SchedulingInfo::new(
+                ns.easy.memory.into(),
+                ns.easy.interval.round().max(1.0) as u32,
+                ns.easy.interval,
+            ),
         }
     }
 }
 
+/// Anki's fuzz bands: each adds `factor` days of spread per day of interval
+/// falling between `start` and `end`
+const FUZZ_RANGES: [(f32, f32, f32); 3] = [
+    (2.5, 7.0, 0.15),
+    (7.0, 20.0, 0.1),
+    (20.0, f32::MAX, 0.05),
+];
+
+/// Number of days an interval may be fuzzed in either direction
+/// Intervals shorter than 2.5 days are never fuzzed
+fn fuzz_delta(interval: f32) -> f32 {
+    if interval < 2.5 {
+        return 0.0;
+    }
+    FUZZ_RANGES.iter().fold(1.0, |delta, &(start, end, factor)| {
+        delta + factor * (interval.min(end) - start).max(0.0)
+    })
+}
+
+/// Inclusive range of days a fuzzed interval may fall in
+fn fuzz_bounds(interval: f32) -> (u32, u32) {
+    let delta = fuzz_delta(interval);
+    let lower = ((interval - delta).round() as u32).max(1);
+    let upper = ((interval + delta).round() as u32).max(lower);
+    (lower, upper)
+}
+
+/// Pick a day within the fuzz bounds, `fuzz_factor` being uniform in [0, 1)
+fn apply_fuzz_factor(interval: f32, fuzz_factor: f32) -> u32 {
+    let (lower, upper) = fuzz_bounds(interval);
+    let span = (upper - lower + 1) as f32;
+    (lower + (fuzz_factor * span) as u32).min(upper)
+}
+
+/// SplitMix64 step, used to derive fuzz factors from a seed
+fn next_random(state: &mut u64) -> f32 {
+    *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    // Top 24 bits give an exactly representable f32 in [0, 1)
+    (z >> 40) as f32 / (1u64 << 24) as f32
+}
+
+/// Seed from the clock when the caller didn't ask for reproducible fuzz
+fn random_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+/// Settings for a review workload simulation
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct SimConfig {
+    /// Target retention probability (0.7-0.99, typically 0.9)
+    pub desired_retention: f32,
+    /// New cards introduced each day
+    pub new_cards_per_day: u32,
+    /// Number of days to simulate
+    pub days_to_simulate: u32,
+    /// FSRS parameters (None for defaults)
+    #[uniffi(default = None)]
+    pub parameters: Option<Vec<f32>>,
+    /// Seed for a reproducible run (None for a fixed default)
+    #[uniffi(default = None)]
+    pub seed: Option<u64>,
+}
+
+/// Day-by-day outcome of a workload simulation
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct SimResult {
+    /// New cards studied on each day
+    pub new_cards_per_day: Vec<u32>,
+    /// Reviews due on each day
+    pub reviews_per_day: Vec<u32>,
+    /// Expected number of memorized cards at the end of each day
+    pub memorized_per_day: Vec<f32>,
+}
+
+/// Settings for the optimal retention search
+#[derive(Debug, Clone, uniffi::Record)]
+pub struct OptimalRetentionConfig {
+    /// Number of cards in the simulated deck
+    pub deck_size: u32,
+    /// Number of days to simulate
+    pub days_to_simulate: u32,
+    /// Maximum study time per day in seconds
+    #[uniffi(default = 1800.0)]
+    pub max_seconds_per_day: f32,
+    /// New cards introduced each day (None for no limit)
+    #[uniffi(default = None)]
+    pub new_cards_per_day: Option<u32>,
+}
+
+/// Suggested retention is always within this range
+const OPTIMAL_RETENTION_RANGE: (f32, f32) = (0.7, 0.97);
+
+/// Check that a parameter set has a length fsrs-rs accepts (empty for defaults, 17 or 19)
+fn check_parameters(parameters: &[f32]) -> Result<(), FSRSError> {
+    if matches!(parameters.len(), 0 | 17 | 19) {
+        Ok(())
+    } else {
+        Err(FSRSError::InvalidParameters {
+            message: format!("expected 0, 17 or 19 parameters, got {}", parameters.len()),
+        })
+    }
+}
+
+/// Accepted range for `desired_retention`
+const DESIRED_RETENTION_RANGE: (f32, f32) = (0.70, 0.99);
+
+/// Longest gap between reviews accepted (100 years, FSRS's own interval ceiling)
+const MAX_DAYS_ELAPSED: u32 = 36_500;
+
+/// Check the inputs shared by `next_states` and `schedule`
+fn check_review_inputs(desired_retention: f32, days_elapsed: u32) -> Result<(), FSRSError> {
+    let (min, max) = DESIRED_RETENTION_RANGE;
+    if !desired_retention.is_finite() || !(min..=max).contains(&desired_retention) {
+        return Err(FSRSError::InvalidParameters {
+            message: format!(
+                "desired_retention must be between {} and {}, got {}",
+                min, max, desired_retention
+            ),
+        });
+    }
+    if days_elapsed > MAX_DAYS_ELAPSED {
+        return Err(FSRSError::InvalidParameters {
+            message: format!(
+                "days_elapsed must be at most {}, got {}",
+                MAX_DAYS_ELAPSED, days_elapsed
+            ),
+        });
+    }
+    Ok(())
+}
+
 /// Error types for FSRS operations
 #[derive(Debug, Clone, thiserror::Error, uniffi::Error)]
 pub enum FSRSError {
@@ -99,16 +282,41 @@ pub enum FSRSError {
 /// * `memory` - Current memory state (None for new card)
 /// * `desired_retention` - Target retention probability (0.7-0.99, typically 0.9)
 /// * `days_elapsed` - Days since last review (0 for new card)
+/// * `apply_fuzz` - Spread Hard/Good/Easy intervals the way Anki does so reviews don't bunch up
+/// * `seed` - Seed for reproducible fuzz (random when None)
+/// * `maximum_interval` - Cap on every interval in days, like Anki's "maximum interval" (None for no cap)
+/// * `enable_short_term` - Let same-day reviews (`days_elapsed` 0) change stability; when off they leave it as is
 ///
 /// # Returns
 /// * `NextStates` containing scheduling info for each rating option (Again, Hard, Good, Easy)
-#[uniffi::export]
+#[uniffi::export(default(
+    apply_fuzz = false,
+    seed = None,
+    maximum_interval = None,
+    enable_short_term = true
+))]
 pub fn next_states(
     memory: Option<MemoryState>,
     desired_retention: f32,
     days_elapsed: u32,
+    apply_fuzz: bool,
+    seed: Option<u64>,
+    maximum_interval: Option<u32>,
+    enable_short_term: bool,
 ) -> Result<NextStates, FSRSError> {
-    let fsrs = FSRS::new(Some(&[])).map_err(|e| FSRSError::InvalidParameters {
+    check_review_inputs(desired_retention, days_elapsed)?;
+
+    let parameters = if enable_short_term {
+        Vec::new()
+    } else {
+        // fsrs-rs has no switch for this; zeroed short-term weights (w17, w18)
+        // make same-day reviews keep the current stability, as Anki does
+        let mut parameters = DEFAULT_PARAMETERS.to_vec();
+        parameters[17] = 0.0;
+        parameters[18] = 0.0;
+        parameters
+    };
+    let fsrs = FSRS::new(Some(&parameters)).map_err(|e| FSRSError::InvalidParameters {
         message: e.to_string(),
     })?;
 
@@ -120,20 +328,60 @@ pub fn next_states(
             message: e.to_string(),
         })?;
 
-    Ok(states.into())
+    let mut next: NextStates = states.clone().into();
+
+    if apply_fuzz {
+        // Again is never fuzzed. Like Anki, one factor covers the whole card, and
+        // each rating stays at least a day past the one before where bands overlap
+        let fuzz_factor = next_random(&mut seed.unwrap_or_else(random_seed));
+        let hard = apply_fuzz_factor(states.hard.interval, fuzz_factor);
+        let good = apply_fuzz_factor(states.good.interval, fuzz_factor).max(hard + 1);
+        let easy = apply_fuzz_factor(states.easy.interval, fuzz_factor).max(good + 1);
+        next.hard.set_interval(hard);
+        next.good.set_interval(good);
+        next.easy.set_interval(easy);
+    }
+
+    if let Some(maximum) = maximum_interval {
+        // Hard/Good/Easy keep their one-day minimum even under a smaller cap
+        next.again.set_interval(next.again.interval.min(maximum));
+        for info in [&mut next.hard, &mut next.good, &mut next.easy] {
+            info.set_interval(info.interval.min(maximum.max(1)));
+        }
+    }
+
+    Ok(next)
 }
 
 /// Schedule a card review with a specific rating
 ///
 /// Convenience function that calls next_states and returns only the result for the given rating.
-#[uniffi::export]
+#[uniffi::export(default(
+    apply_fuzz = false,
+    seed = None,
+    maximum_interval = None,
+    enable_short_term = true
+))]
+#[allow(clippy::too_many_arguments)]
 pub fn schedule(
     memory: Option<MemoryState>,
     rating: Rating,
     desired_retention: f32,
     days_elapsed: u32,
+    apply_fuzz: bool,
+    seed: Option<u64>,
+    maximum_interval: Option<u32>,
+    enable_short_term: bool,
 ) -> Result<SchedulingInfo, FSRSError> {
-    let states = next_states(memory, desired_retention, days_elapsed)?;
+    let states = next_states(
+        memory,
+        desired_retention,
+        days_elapsed,
+        apply_fuzz,
+        seed,
+        maximum_interval,
+        enable_short_term,
+    )?;
 
     Ok(match rating {
         Rating::Again => states.again,
@@ -143,6 +391,224 @@ pub fn schedule(
     })
 }
 
+/// Derive a card's memory state by replaying its review history
+///
+/// # Arguments
+/// * `reviews` - Past reviews, oldest first
+///
+/// # Returns
+/// * `MemoryState` after the last review
+#[uniffi::export]
+pub fn memory_state_from_history(reviews: Vec<FsrsReview>) -> Result<MemoryState, FSRSError> {
+    if reviews.is_empty() {
+        return Err(FSRSError::InvalidParameters {
+            message: "review history is empty".to_string(),
+        });
+    }
+
+    let fsrs = FSRS::new(Some(&[])).map_err(|e| FSRSError::InvalidParameters {
+        message: e.to_string(),
+    })?;
+
+    let item = FSRSItem {
+        reviews: reviews.into_iter().map(FSRSReview::from).collect(),
+    };
+
+    fsrs.memory_state(item, None)
+        .map(MemoryState::from)
+        .map_err(|e| FSRSError::ComputationError {
+            message: e.to_string(),
+        })
+}
+
+/// Approximate a memory state from SM-2 scheduling data
+///
+/// Used to migrate cards that have no usable review history.
+///
+/// # Arguments
+/// * `ease_factor` - SM-2 ease as a multiplier (e.g. 2.5, not Anki's stored 2500)
+/// * `interval` - Current SM-2 interval in days
+/// * `sm2_retention` - Retention the SM-2 schedule achieved (0.0 - 1.0, typically 0.9)
+#[uniffi::export]
+pub fn memory_state_from_sm2(
+    ease_factor: f32,
+    interval: f32,
+    sm2_retention: f32,
+) -> Result<MemoryState, FSRSError> {
+    if !(sm2_retention > 0.0 && sm2_retention < 1.0) {
+        return Err(FSRSError::InvalidParameters {
+            message: format!("sm2_retention must be between 0 and 1, got {}", sm2_retention),
+        });
+    }
+
+    let fsrs = FSRS::new(Some(&[])).map_err(|e| FSRSError::InvalidParameters {
+        message: e.to_string(),
+    })?;
+
+    fsrs.memory_state_from_sm2(ease_factor, interval, sm2_retention)
+        .map(MemoryState::from)
+        .map_err(|e| FSRSError::ComputationError {
+            message: e.to_string(),
+        })
+}
+
+/// Retention assumed for an imported SM-2 schedule, matching Anki's default
+const ANKI_SM2_RETENTION: f32 = 0.9;
+
+/// Initial memory state for a card scheduled by Anki's SM-2, from its stored fields
+///
+/// # Arguments
+/// * `ease_factor` - Ease as Anki stores it (`cards.factor`, e.g. 2500 for 250%)
+/// * `interval` - Interval as Anki stores it (`cards.ivl`): days, or negative
+///   seconds while learning
+/// * `reps` - Number of reviews (`cards.reps`)
+/// * `lapses` - Number of times the card was forgotten (`cards.lapses`)
+///
+/// # Returns
+/// * `None` for cards with no graduated SM-2 state to start from (never reviewed,
+///   still in learning, or never recalled), which FSRS should treat as new
+#[uniffi::export]
+pub fn from_anki_card(
+    ease_factor: u32,
+    interval: i32,
+    reps: u32,
+    lapses: u32,
+) -> Option<MemoryState> {
+    if reps == 0 || lapses >= reps || interval <= 0 || ease_factor == 0 {
+        return None;
+    }
+    memory_state_from_sm2(ease_factor as f32 / 1000.0, interval as f32, ANKI_SM2_RETENTION).ok()
+}
+
+/// Schedule a review of a card imported from Anki, starting from its SM-2 fields
+///
+/// Convenience function combining `from_anki_card` and `schedule`, with no fuzz,
+/// no interval cap and short-term scheduling on.
+///
+/// # Arguments
+/// * `ease_factor`, `interval`, `reps`, `lapses` - Anki's stored fields, see `from_anki_card`
+/// * `rating` - Rating given at this review
+/// * `desired_retention` - Target retention probability (0.7-0.99, typically 0.9)
+/// * `days_elapsed` - Days since the last review (0 for a new card)
+#[uniffi::export]
+pub fn schedule_anki_card(
+    ease_factor: u32,
+    interval: i32,
+    reps: u32,
+    lapses: u32,
+    rating: Rating,
+    desired_retention: f32,
+    days_elapsed: u32,
+) -> Result<SchedulingInfo, FSRSError> {
+    let memory = from_anki_card(ease_factor, interval, reps, lapses);
+    schedule(memory, rating, desired_retention, days_elapsed, false, None, None, true)
+}
+
+/// Forecast daily workload when adding a fixed number of new cards per day
+///
+/// # Arguments
+/// * `config` - Retention target, daily new cards and simulation length
+///
+/// # Returns
+/// * `SimResult` with per-day new card, review and memorized counts
+#[uniffi::export]
+pub fn simulate(config: SimConfig) -> Result<SimResult, FSRSError> {
+    if !(config.desired_retention > 0.0 && config.desired_retention < 1.0) {
+        return Err(FSRSError::InvalidParameters {
+            message: format!(
+                "desired_retention must be between 0 and 1, got {}",
+                config.desired_retention
+            ),
+        });
+    }
+    if config.new_cards_per_day == 0 || config.days_to_simulate == 0 {
+        return Err(FSRSError::InvalidParameters {
+            message: "new_cards_per_day and days_to_simulate must be positive".to_string(),
+        });
+    }
+
+    let days = config.days_to_simulate as usize;
+    let new_per_day = config.new_cards_per_day as usize;
+    let sim_config = SimulatorConfig {
+        deck_size: new_per_day.saturating_mul(days),
+        learn_span: days,
+        learn_limit: new_per_day,
+        // Forecast the full workload rather than capping it by study time
+        max_cost_perday: f32::MAX,
+        ..SimulatorConfig::default()
+    };
+    let parameters = config.parameters.unwrap_or_default();
+
+    let result = fsrs::simulate(
+        &sim_config,
+        &parameters,
+        config.desired_retention,
+        config.seed,
+        None,
+    )
+    .map_err(|e| match e {
+        fsrs::FSRSError::InvalidParameters => FSRSError::InvalidParameters {
+            message: e.to_string(),
+        },
+        _ => FSRSError::ComputationError {
+            message: e.to_string(),
+        },
+    })?;
+
+    Ok(SimResult {
+        new_cards_per_day: result.learn_cnt_per_day.iter().map(|&n| n as u32).collect(),
+        reviews_per_day: result.review_cnt_per_day.iter().map(|&n| n as u32).collect(),
+        memorized_per_day: result.memorized_cnt_per_day.to_vec(),
+    })
+}
+
+/// Find the desired retention that minimizes review workload per memorized card
+///
+/// # Arguments
+/// * `parameters` - FSRS parameters (empty for defaults)
+/// * `config` - Deck size, simulation length and daily limits
+///
+/// # Returns
+/// * Suggested desired retention (0.7 - 0.97)
+#[uniffi::export]
+pub fn optimal_retention(
+    parameters: Vec<f32>,
+    config: OptimalRetentionConfig,
+) -> Result<f32, FSRSError> {
+    check_parameters(&parameters)?;
+    if config.days_to_simulate == 0 || config.deck_size == 0 {
+        return Err(FSRSError::InvalidParameters {
+            message: "deck_size and days_to_simulate must be positive".to_string(),
+        });
+    }
+
+    let fsrs = FSRS::new(Some(&parameters)).map_err(|e| FSRSError::InvalidParameters {
+        message: e.to_string(),
+    })?;
+
+    let sim_config = SimulatorConfig {
+        deck_size: config.deck_size as usize,
+        learn_span: config.days_to_simulate as usize,
+        max_cost_perday: config.max_seconds_per_day,
+        learn_limit: config.new_cards_per_day.map_or(usize::MAX, |n| n as usize),
+        ..SimulatorConfig::default()
+    };
+
+    let retention = fsrs
+        .optimal_retention(&sim_config, &parameters, |_| true)
+        .map_err(|e| match e {
+            fsrs::FSRSError::OptimalNotFound => FSRSError::ComputationError {
+                message: "optimal retention search did not converge".to_string(),
+            },
+            _ => FSRSError::ComputationError {
+                message: e.to_string(),
+            },
+        })?;
+
+    let (min, max) = OPTIMAL_RETENTION_RANGE;
+    Ok(retention.clamp(min, max))
+}
+
 /// Calculate current retrievability (recall probability)
 ///
 /// # Arguments
@@ -153,14 +619,50 @@ pub fn schedule(
 /// * Probability of recall (0.0 - 1.0)
 #[uniffi::export]
 pub fn current_retrievability(stability: f32, days_elapsed: u32) -> f32 {
+    retrievability(stability, days_elapsed, DEFAULT_DECAY)
+}
+
+/// FSRS-5 default decay
+const DEFAULT_DECAY: f32 = 0.5;
+
+/// FSRS retrievability formula: R = (1 + days/S * c)^(-decay)
+/// c is chosen so that R = 0.9 when days == S (19/81 for the default decay)
+fn retrievability(stability: f32, days_elapsed: u32, decay: f32) -> f32 {
     if stability <= 0.0 {
         return 0.0;
     }
-    // FSRS retrievability formula: R = (1 + days/S * c)^(-1/decay)
-    // Using FSRS-5 default decay of 0.5 for now
-    let decay = 0.5_f32;
-    let factor = 19.0_f32 / 81.0_f32; // c = 19/81 for FSRS-5
-    (1.0 + (days_elapsed as f32) / stability * factor).powf(-1.0 / decay)
+    let factor = 0.9_f32.powf(-1.0 / decay) - 1.0;
+    (1.0 + (days_elapsed as f32) / stability * factor).powf(-decay)
+}
+
+/// A card whose retrievability should be computed in a batch
+#[derive(Debug, Clone, Copy, uniffi::Record)]
+pub struct RetrievabilityInput {
+    /// Current stability value from memory state
+    pub stability: f32,
+    /// Days since last review
+    pub days_elapsed: u32,
+    /// Decay for the card's parameters (None for the FSRS-5 default)
+    #[uniffi(default = None)]
+    pub decay: Option<f32>,
+}
+
+/// Calculate retrievability for many cards in one call
+///
+/// # Returns
+/// * Probability of recall for each input, in the same order
+#[uniffi::export]
+pub fn retrievability_batch(inputs: Vec<RetrievabilityInput>) -> Vec<f32> {
+    inputs
+        .iter()
+        .map(|input| {
+            retrievability(
+                input.stability,
+                input.days_elapsed,
+                input.decay.unwrap_or(DEFAULT_DECAY),
+            )
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -169,9 +671,9 @@ mod tests {
 
     #[test]
     fn test_new_card_scheduling() {
-        let states = next_states(None, 0.9, 0).unwrap();
-        // New card should have short intervals
-        assert!(states.again.interval >= 1);
+        let states = next_states(None, 0.9, 0, false, None, None, true).unwrap();
+        // New card should have short intervals; Again is due the same day
+        assert_eq!(states.again.interval, 0);
         assert!(states.good.interval >= 1);
         // Easy should have longest interval
         assert!(states.easy.interval >= states.good.interval);
@@ -185,7 +687,7 @@ mod tests {
             difficulty: 0.3,
         };
 
-        let states = next_states(Some(memory), 0.9, 5).unwrap();
+        let states = next_states(Some(memory), 0.9, 5, false, None, None, true).unwrap();
         // Should have increasing intervals
         assert!(states.again.interval < states.hard.interval);
         assert!(states.hard.interval <= states.good.interval);
@@ -194,7 +696,7 @@ mod tests {
 
     #[test]
     fn test_schedule_single_rating() {
-        let info = schedule(None, Rating::Good, 0.9, 0).unwrap();
+        let info = schedule(None, Rating::Good, 0.9, 0, false, None, None, true).unwrap();
         assert!(info.interval >= 1);
         assert!(info.memory.stability > 0.0);
     }
@@ -213,6 +715,302 @@ mod tests {
         assert!(r10 < r5);
     }
 
+    #[test]
+    fn test_fuzz_bounds() {
+        // Short intervals are left alone
+        assert_eq!(fuzz_bounds(2.0), (2, 2));
+        // 10 days: 1 + 0.15 * 4.5 + 0.1 * 3 = 1.975 days either way
+        assert_eq!(fuzz_bounds(10.0), (8, 12));
+        // 100 days: 1 + 0.675 + 1.3 + 0.05 * 80 = 6.975 days either way
+        assert_eq!(fuzz_bounds(100.0), (93, 107));
+
+        assert_eq!(apply_fuzz_factor(10.0, 0.0), 8);
+        assert_eq!(apply_fuzz_factor(10.0, 0.999), 12);
+    }
+
+    #[test]
+    fn test_fuzzed_intervals_stay_in_band() {
+        let memory = MemoryState {
+            stability: 40.0,
+            difficulty: 5.0,
+        };
+        let plain = next_states(Some(memory), 0.9, 30, false, None, None, true).unwrap();
+        let raw = FSRS::new(Some(&[]))
+            .unwrap()
+            .next_states(Some(memory.into()), 0.9, 30)
+            .unwrap();
+
+        for seed in 0..200 {
+            let fuzzed = next_states(Some(memory), 0.9, 30, true, Some(seed), None, true).unwrap();
+            assert_eq!(fuzzed.again.interval, plain.again.interval);
+            for (interval, raw_interval) in [
+                (fuzzed.hard.interval, raw.hard.interval),
+                (fuzzed.good.interval, raw.good.interval),
+                (fuzzed.easy.interval, raw.easy.interval),
+            ] {
+                let (lower, upper) = fuzz_bounds(raw_interval);
+                assert!((lower..=upper).contains(&interval));
+            }
+        }
+
+        // The same seed gives the same result
+        let a = next_states(Some(memory), 0.9, 30, true, Some(7), None, true).unwrap();
+        let b = next_states(Some(memory), 0.9, 30, true, Some(7), None, true).unwrap();
+        assert_eq!(a.good.interval, b.good.interval);
+    }
+
+    #[test]
+    fn test_fuzz_keeps_rating_order() {
+        for (stability, days_elapsed, maximum_interval) in
+            [(3.0, 1, None), (1.0, 0, None), (10.0, 5, None), (60.0, 40, Some(50))]
+        {
+            let memory = MemoryState {
+                stability,
+                difficulty: 5.0,
+            };
+            for seed in 0..200 {
+                let next = next_states(
+                    Some(memory),
+                    0.9,
+                    days_elapsed,
+                    true,
+                    Some(seed),
+                    maximum_interval,
+                    true,
+                )
+                .unwrap();
+                assert!(next.hard.interval >= 1);
+                assert!(next.hard.interval <= next.good.interval);
+                assert!(next.good.interval <= next.easy.interval);
+                assert!(next.easy.interval <= maximum_interval.unwrap_or(u32::MAX));
+            }
+        }
+    }
+
+    #[test]
+    fn test_retrievability_at_due() {
+        let memory = MemoryState {
+            stability: 10.0,
+            difficulty: 5.0,
+        };
+        let states = next_states(Some(memory), 0.9, 10, false, None, Some(30), true).unwrap();
+        for info in [states.again, states.hard, states.good, states.easy] {
+            let expected = current_retrievability(info.memory.stability, info.interval);
+            assert_eq!(info.retrievability_at_due, expected);
+        }
+        // Scheduled at the desired retention, give or take rounding
+        assert!((states.good.retrievability_at_due - 0.9).abs() < 0.05);
+    }
+
+    #[test]
+    fn test_raw_interval_days() {
+        let raw = FSRS::new(Some(&[])).unwrap().next_states(None, 0.9, 0).unwrap();
+        let states = next_states(None, 0.9, 0, false, None, None, true).unwrap();
+
+        assert_eq!(states.again.raw_interval_days, raw.again.interval);
+        assert_eq!(states.easy.raw_interval_days, raw.easy.interval);
+        // Same-day Again keeps its fraction of a day
+        assert!(states.again.raw_interval_days > 0.0);
+        assert_eq!(states.again.interval, 0);
+    }
+
+    #[test]
+    fn test_short_term_toggle() {
+        let memory = MemoryState {
+            stability: 5.0,
+            difficulty: 5.0,
+        };
+        let on = next_states(Some(memory), 0.9, 0, false, None, None, true).unwrap();
+        let off = next_states(Some(memory), 0.9, 0, false, None, None, false).unwrap();
+
+        // Same-day reviews move stability only in short-term mode
+        assert_ne!(on.good.memory.stability, memory.stability);
+        assert_eq!(off.good.memory.stability, memory.stability);
+        assert_eq!(off.again.memory.stability, memory.stability);
+        assert!(on.easy.interval > off.easy.interval);
+
+        // Reviews on later days are unaffected
+        let on = next_states(Some(memory), 0.9, 5, false, None, None, true).unwrap();
+        let off = next_states(Some(memory), 0.9, 5, false, None, None, false).unwrap();
+        assert_eq!(on.good.interval, off.good.interval);
+    }
+
+    #[test]
+    fn test_invalid_review_inputs() {
+        let invalid = |retention, days| {
+            matches!(
+                next_states(None, retention, days, false, None, None, true),
+                Err(FSRSError::InvalidParameters { .. })
+            )
+        };
+
+        assert!(!invalid(0.70, 0));
+        assert!(!invalid(0.99, 0));
+        assert!(!invalid(0.9, MAX_DAYS_ELAPSED));
+
+        assert!(invalid(0.69, 0));
+        assert!(invalid(0.0, 0));
+        assert!(invalid(1.5, 0));
+        assert!(invalid(f32::NAN, 0));
+        assert!(invalid(0.9, MAX_DAYS_ELAPSED + 1));
+        assert!(invalid(0.9, u32::MAX));
+
+        match next_states(None, 1.5, 0, false, None, None, true) {
+            Err(FSRSError::InvalidParameters { message }) => assert!(message.contains("1.5")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_maximum_interval() {
+        let memory = MemoryState {
+            stability: 400.0,
+            difficulty: 5.0,
+        };
+        let capped = next_states(Some(memory), 0.9, 400, false, None, Some(365), true).unwrap();
+        assert_eq!(capped.easy.interval, 365);
+        assert!(capped.hard.interval <= 365);
+
+        // A zero cap still leaves Hard/Good/Easy at least a day out
+        let zero = next_states(Some(memory), 0.9, 400, false, None, Some(0), true).unwrap();
+        assert_eq!(zero.again.interval, 0);
+        assert_eq!(zero.hard.interval, 1);
+        assert_eq!(zero.easy.interval, 1);
+    }
+
+    #[test]
+    fn test_memory_state_from_history() {
+        let reviews = vec![
+            FsrsReview { rating: Rating::Good, delta_t: 0 },
+            FsrsReview { rating: Rating::Good, delta_t: 3 },
+        ];
+        let memory = memory_state_from_history(reviews).unwrap();
+        assert!(memory.stability > 0.0);
+
+        // A lapse leaves the card less stable than remembering it
+        let lapsed = memory_state_from_history(vec![
+            FsrsReview { rating: Rating::Good, delta_t: 0 },
+            FsrsReview { rating: Rating::Again, delta_t: 3 },
+        ])
+        .unwrap();
+        assert!(lapsed.stability < memory.stability);
+
+        assert!(matches!(
+            memory_state_from_history(Vec::new()),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_memory_state_from_sm2() {
+        let short = memory_state_from_sm2(2.5, 5.0, 0.9).unwrap();
+        let long = memory_state_from_sm2(2.5, 50.0, 0.9).unwrap();
+        assert!(long.stability > short.stability);
+
+        assert!(matches!(
+            memory_state_from_sm2(2.5, 5.0, 1.5),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_anki_card() {
+        let memory = from_anki_card(2500, 30, 8, 1).unwrap();
+        let expected = memory_state_from_sm2(2.5, 30.0, 0.9).unwrap();
+        assert_eq!(memory.stability, expected.stability);
+        assert_eq!(memory.difficulty, expected.difficulty);
+
+        // New, still learning (negative seconds) and never-recalled cards start fresh
+        assert!(from_anki_card(0, 0, 0, 0).is_none());
+        assert!(from_anki_card(2500, -600, 2, 0).is_none());
+        assert!(from_anki_card(2500, 1, 2, 2).is_none());
+    }
+
+    #[test]
+    fn test_schedule_anki_card() {
+        let new = schedule_anki_card(0, 0, 0, 0, Rating::Good, 0.9, 0).unwrap();
+        let expected = schedule(None, Rating::Good, 0.9, 0, false, None, None, true).unwrap();
+        assert_eq!(new.interval, expected.interval);
+
+        let review = schedule_anki_card(2500, 30, 8, 1, Rating::Good, 0.9, 30).unwrap();
+        assert!(review.interval > 30);
+    }
+
+    #[test]
+    fn test_simulate() {
+        let config = SimConfig {
+            desired_retention: 0.9,
+            new_cards_per_day: 20,
+            days_to_simulate: 30,
+            parameters: None,
+            seed: Some(1),
+        };
+        let result = simulate(config.clone()).unwrap();
+
+        assert_eq!(result.new_cards_per_day.len(), 30);
+        assert!(result.new_cards_per_day.iter().all(|&n| n == 20));
+        assert!(result.reviews_per_day.iter().sum::<u32>() > 0);
+        assert!(result.memorized_per_day[29] > result.memorized_per_day[0]);
+
+        assert!(matches!(
+            simulate(SimConfig { days_to_simulate: 0, ..config.clone() }),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+        assert!(matches!(
+            simulate(SimConfig { desired_retention: 1.2, ..config.clone() }),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+        assert!(matches!(
+            simulate(SimConfig { parameters: Some(vec![1.0; 3]), ..config }),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_optimal_retention() {
+        let config = OptimalRetentionConfig {
+            deck_size: 100,
+            days_to_simulate: 365,
+            max_seconds_per_day: 1800.0,
+            new_cards_per_day: Some(10),
+        };
+        let retention = optimal_retention(Vec::new(), config.clone()).unwrap();
+        assert!((0.7..=0.97).contains(&retention));
+
+        assert!(matches!(
+            optimal_retention(vec![1.0; 5], config.clone()),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+        assert!(matches!(
+            optimal_retention(Vec::new(), OptimalRetentionConfig { days_to_simulate: 0, ..config }),
+            Err(FSRSError::InvalidParameters { .. })
+        ));
+    }
+
+    #[test]
+    fn test_retrievability_at_stability() {
+        // By definition, recall is 90% once `stability` days have passed
+        assert!((current_retrievability(10.0, 10) - 0.9).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_retrievability_batch() {
+        let input = |stability, days_elapsed, decay| RetrievabilityInput {
+            stability,
+            days_elapsed,
+            decay,
+        };
+        let results = retrievability_batch(vec![
+            input(10.0, 5, None),
+            input(0.0, 5, None),
+            input(10.0, 10, Some(0.2)),
+        ]);
+
+        assert_eq!(results[0], current_retrievability(10.0, 5));
+        assert_eq!(results[1], 0.0);
+        assert!((results[2] - 0.9).abs() < 0.001);
+    }
+
     #[test]
     fn test_retrievability_edge_cases() {
         // Zero stability should return 0
@@ -228,7 +1026,7 @@ mod integration_tests {
     #[test]
     fn test_print_new_card_scheduling() {
         println!("\n=== NEW CARD (first review) ===");
-        let states = next_states(None, 0.9, 0).unwrap();
+        let states = next_states(None, 0.9, 0, false, None, None, true).unwrap();
         
         println!("Again: {} day(s), stability={:.2}, difficulty={:.2}", 
             states.again.interval, states.again.memory.stability, states.again.memory.difficulty);
@@ -248,7 +1046,7 @@ mod integration_tests {
         let retention = 0.9;
         
         for review_num in 1..=6 {
-            let states = next_states(memory, retention, 0).unwrap();
+            let states = next_states(memory, retention, 0, false, None, None, true).unwrap();
             let info = states.good;
             
             println!("Review {}: interval={} day(s), stability={:.1}, difficulty={:.2}",
@@ -272,7 +1070,6 @@ mod integration_tests {
 
 #[cfg(test)]
 mod raw_interval_test {
-    use super::*;
     use fsrs::FSRS;
 
     #[test]